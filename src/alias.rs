@@ -0,0 +1,76 @@
+use anyhow::Result;
+use std::io::Write;
+
+use crate::{shell::Shell, vars::quote};
+
+impl Shell {
+    /// Runs `alias [name[=value]...]`, which defines aliases or prints them.
+    pub fn builtin_alias(&mut self, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        if args.is_empty() {
+            for (name, value) in &self.aliases {
+                writeln!(out, "alias {}={}", name, quote(value))?;
+            }
+            return Ok(0);
+        }
+        let mut status = 0;
+        for arg in args {
+            match arg.split_once('=') {
+                Some((name, value)) if !name.is_empty() && !name.contains('/') => {
+                    self.aliases.insert(name.to_owned(), value.to_owned());
+                }
+                Some(_) => {
+                    eprintln!("alias: `{arg}`: invalid alias name");
+                    status = 1;
+                }
+                None => match self.aliases.get(arg) {
+                    Some(value) => writeln!(out, "alias {}={}", arg, quote(value))?,
+                    None => {
+                        eprintln!("alias: {arg}: not found");
+                        status = 1;
+                    }
+                },
+            }
+        }
+        Ok(status)
+    }
+
+    /// Runs `unalias [-a] name...`.
+    pub fn builtin_unalias(&mut self, args: &[String]) -> Result<i32> {
+        if args.first().is_some_and(|arg| arg == "-a") {
+            self.aliases.clear();
+            return Ok(0);
+        }
+        let mut status = 0;
+        for name in args {
+            if self.aliases.remove(name).is_none() {
+                eprintln!("unalias: {name}: not found");
+                status = 1;
+            }
+        }
+        Ok(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliases_stand_for_any_command_text() {
+        let mut shell = Shell::new().unwrap();
+        shell
+            .handel_command("alias up='tr a-z A-Z' greet='echo hi | up && echo there; '")
+            .unwrap();
+        shell.handel_command("out=$(greet)").unwrap();
+        assert_eq!(shell.vars.get("out"), Some("HI\nthere"));
+
+        let mut out = Vec::new();
+        let args = ["up".to_owned(), "bad/name=x".to_owned()];
+        assert_eq!(shell.builtin_alias(&args, &mut out).unwrap(), 1);
+        assert_eq!(String::from_utf8(out).unwrap(), "alias up='tr a-z A-Z'\n");
+
+        shell.handel_command("unalias up").unwrap();
+        shell.handel_command("out=$(up 2>&1 || echo gone)").unwrap();
+        assert!(shell.vars.get("out").unwrap().ends_with("gone"));
+    }
+}
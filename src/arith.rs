@@ -0,0 +1,534 @@
+use anyhow::{anyhow, bail, Result};
+
+use crate::shell::Shell;
+
+/// How deeply variables whose values are expressions themselves are evaluated.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinOp {
+    Comma,
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Num(i64),
+    Var(String),
+    /// `-`, `+`, `!` or `~` applied to an operand.
+    Unary(char, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// `name = value`, or `name op= value`.
+    Assign(String, Option<BinOp>, Box<Expr>),
+    /// `++name`, `name--` and friends.
+    Step {
+        name: String,
+        delta: i64,
+        prefix: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Num(i64),
+    Name(String),
+    Op(&'static str),
+    End,
+}
+
+/// Operators, longest first so that `<<=` is not read as `<<`.
+const OPS: [&str; 39] = [
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "*=", "/=",
+    "%=", "+=", "-=", "&=", "^=", "|=", "*", "/", "%", "+", "-", "<", ">", "&", "^", "|", "!", "~",
+    "?", ":", "=", ",", "(", ")",
+];
+
+/// Binary operators with their precedence; higher binds tighter.
+fn binary(op: &str) -> Option<(BinOp, u8)> {
+    Some(match op {
+        "," => (BinOp::Comma, 1),
+        "||" => (BinOp::Or, 4),
+        "&&" => (BinOp::And, 5),
+        "|" => (BinOp::BitOr, 6),
+        "^" => (BinOp::BitXor, 7),
+        "&" => (BinOp::BitAnd, 8),
+        "==" => (BinOp::Eq, 9),
+        "!=" => (BinOp::Ne, 9),
+        "<" => (BinOp::Lt, 10),
+        "<=" => (BinOp::Le, 10),
+        ">" => (BinOp::Gt, 10),
+        ">=" => (BinOp::Ge, 10),
+        "<<" => (BinOp::Shl, 11),
+        ">>" => (BinOp::Shr, 11),
+        "+" => (BinOp::Add, 12),
+        "-" => (BinOp::Sub, 12),
+        "*" => (BinOp::Mul, 13),
+        "/" => (BinOp::Div, 13),
+        "%" => (BinOp::Rem, 13),
+        "**" => (BinOp::Pow, 14),
+        _ => return None,
+    })
+}
+
+/// The operator of an assignment such as `+=`, `None` for a plain `=`.
+fn assignment(op: &str) -> Option<Option<BinOp>> {
+    match op {
+        "=" => Some(None),
+        _ => {
+            let (bin, _) = binary(op.strip_suffix('=')?)?;
+            matches!(
+                bin,
+                BinOp::Mul
+                    | BinOp::Div
+                    | BinOp::Rem
+                    | BinOp::Add
+                    | BinOp::Sub
+                    | BinOp::Shl
+                    | BinOp::Shr
+                    | BinOp::BitAnd
+                    | BinOp::BitXor
+                    | BinOp::BitOr
+            )
+            .then_some(Some(bin))
+        }
+    }
+}
+
+/// Parses an integer constant: decimal, `0x` hex, `0` octal or `base#digits`.
+fn number(text: &str) -> Option<i64> {
+    let (base, digits) = if let Some((base, digits)) = text.split_once('#') {
+        (
+            base.parse().ok().filter(|base| (2..=64).contains(base))?,
+            digits,
+        )
+    } else if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        (16, hex)
+    } else if text.len() > 1 && text.starts_with('0') {
+        (8, &text[1..])
+    } else {
+        (10, text)
+    };
+    if digits.is_empty() {
+        return None;
+    }
+    digits.chars().try_fold(0i64, |value, c| {
+        let digit = match c {
+            '0'..='9' => c as i64 - '0' as i64,
+            'a'..='z' => c as i64 - 'a' as i64 + 10,
+            'A'..='Z' if base <= 36 => c as i64 - 'A' as i64 + 10,
+            'A'..='Z' => c as i64 - 'A' as i64 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return None,
+        };
+        (digit < base).then_some(())?;
+        value.checked_mul(base)?.checked_add(digit)
+    })
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let c = rest.chars().next().unwrap();
+        let len = if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && !matches!(c, '#' | '@' | '_'))
+                .unwrap_or(rest.len());
+            let value = number(&rest[..len])
+                .ok_or_else(|| anyhow!("invalid number (error token is \"{}\")", &rest[..len]))?;
+            tokens.push(Token::Num(value));
+            len
+        } else if c == '_' || c.is_ascii_alphabetic() {
+            let len = rest
+                .find(|c: char| c != '_' && !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..len].to_owned()));
+            len
+        } else if let Some(op) = OPS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            op.len()
+        } else {
+            bail!("syntax error: invalid arithmetic operator (error token is \"{rest}\")");
+        };
+        rest = rest[len..].trim_start();
+    }
+    tokens.push(Token::End);
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if token != Token::End {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn expect(&mut self, op: &str) -> Result<()> {
+        match self.next() {
+            Token::Op(found) if found == op => Ok(()),
+            token => bail!(
+                "syntax error: `{op}` expected (error token is \"{}\")",
+                describe(&token)
+            ),
+        }
+    }
+
+    /// Parses operators binding at least as tightly as `min`.
+    fn expr(&mut self, min: u8) -> Result<Expr> {
+        let mut lhs = self.unary()?;
+        while let Token::Op(op) = *self.peek() {
+            if let Some(bin) = assignment(op).filter(|_| min <= 2) {
+                let Expr::Var(name) = lhs else {
+                    bail!("attempted assignment to non-variable (error token is \"{op}\")");
+                };
+                self.next();
+                lhs = Expr::Assign(name, bin, Box::new(self.expr(2)?));
+            } else if op == "?" && min <= 3 {
+                self.next();
+                let then = self.expr(1)?;
+                self.expect(":")?;
+                let otherwise = self.expr(3)?;
+                lhs = Expr::Cond(Box::new(lhs), Box::new(then), Box::new(otherwise));
+            } else {
+                let Some((bin, precedence)) = binary(op).filter(|(_, p)| *p >= min) else {
+                    break;
+                };
+                self.next();
+                // `**` is the only right-associative binary operator.
+                let next = if bin == BinOp::Pow {
+                    precedence
+                } else {
+                    precedence + 1
+                };
+                lhs = Expr::Binary(bin, Box::new(lhs), Box::new(self.expr(next)?));
+            }
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        match self.next() {
+            Token::Num(value) => Ok(Expr::Num(value)),
+            Token::Name(name) => match *self.peek() {
+                Token::Op(op @ ("++" | "--")) => {
+                    self.next();
+                    Ok(Expr::Step {
+                        name,
+                        delta: if op == "++" { 1 } else { -1 },
+                        prefix: false,
+                    })
+                }
+                _ => Ok(Expr::Var(name)),
+            },
+            Token::Op(op @ ("++" | "--")) => match self.next() {
+                Token::Name(name) => Ok(Expr::Step {
+                    name,
+                    delta: if op == "++" { 1 } else { -1 },
+                    prefix: true,
+                }),
+                token => bail!(
+                    "syntax error: variable expected after `{op}` (error token is \"{}\")",
+                    describe(&token)
+                ),
+            },
+            Token::Op(op @ ("-" | "+" | "!" | "~")) => Ok(Expr::Unary(
+                op.chars().next().unwrap(),
+                Box::new(self.unary()?),
+            )),
+            Token::Op("(") => {
+                let expr = self.expr(1)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            token => bail!(
+                "syntax error: operand expected (error token is \"{}\")",
+                describe(&token)
+            ),
+        }
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Num(value) => value.to_string(),
+        Token::Name(name) => name.clone(),
+        Token::Op(op) => (*op).to_owned(),
+        Token::End => String::new(),
+    }
+}
+
+fn parse(text: &str) -> Result<Option<Expr>> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        pos: 0,
+    };
+    if *parser.peek() == Token::End {
+        return Ok(None);
+    }
+    let expr = parser.expr(1)?;
+    match parser.next() {
+        Token::End => Ok(Some(expr)),
+        token => bail!(
+            "syntax error in expression (error token is \"{}\")",
+            describe(&token)
+        ),
+    }
+}
+
+fn overflow() -> anyhow::Error {
+    anyhow!("arithmetic overflow")
+}
+
+fn apply(op: BinOp, lhs: i64, rhs: i64) -> Result<i64> {
+    let value = match op {
+        BinOp::Add => lhs.checked_add(rhs),
+        BinOp::Sub => lhs.checked_sub(rhs),
+        BinOp::Mul => lhs.checked_mul(rhs),
+        BinOp::Div | BinOp::Rem if rhs == 0 => bail!("division by 0"),
+        BinOp::Div => lhs.checked_div(rhs),
+        BinOp::Rem => lhs.checked_rem(rhs),
+        BinOp::Pow if rhs < 0 => bail!("exponent less than 0"),
+        BinOp::Pow => u32::try_from(rhs).ok().and_then(|rhs| lhs.checked_pow(rhs)),
+        BinOp::Shl | BinOp::Shr if !(0..64).contains(&rhs) => bail!("shift count out of range"),
+        BinOp::Shl => lhs
+            .checked_shl(rhs as u32)
+            .filter(|value| value >> rhs == lhs),
+        BinOp::Shr => Some(lhs >> rhs),
+        BinOp::BitAnd => Some(lhs & rhs),
+        BinOp::BitXor => Some(lhs ^ rhs),
+        BinOp::BitOr => Some(lhs | rhs),
+        BinOp::Eq => Some((lhs == rhs).into()),
+        BinOp::Ne => Some((lhs != rhs).into()),
+        BinOp::Lt => Some((lhs < rhs).into()),
+        BinOp::Le => Some((lhs <= rhs).into()),
+        BinOp::Gt => Some((lhs > rhs).into()),
+        BinOp::Ge => Some((lhs >= rhs).into()),
+        BinOp::And | BinOp::Or | BinOp::Comma => unreachable!("evaluated lazily"),
+    };
+    value.ok_or_else(overflow)
+}
+
+impl Shell {
+    /// Evaluates an arithmetic expression, as in `$((...))`, `((...))` and `let`. An empty
+    /// expression is 0.
+    pub fn arith(&mut self, text: &str) -> Result<i64> {
+        self.arith_at(text, 0)
+    }
+
+    fn arith_at(&mut self, text: &str, depth: usize) -> Result<i64> {
+        let result = parse(text).and_then(|expr| match expr {
+            Some(expr) => self.eval(&expr, depth),
+            None => Ok(0),
+        });
+        result.map_err(|err| anyhow!("{}: {err}", text.trim()))
+    }
+
+    fn eval(&mut self, expr: &Expr, depth: usize) -> Result<i64> {
+        Ok(match expr {
+            Expr::Num(value) => *value,
+            Expr::Var(name) => self.arith_var(name, depth)?,
+            Expr::Unary(op, operand) => {
+                let value = self.eval(operand, depth)?;
+                match op {
+                    '-' => value.checked_neg().ok_or_else(overflow)?,
+                    '!' => (value == 0).into(),
+                    '~' => !value,
+                    _ => value,
+                }
+            }
+            Expr::Binary(BinOp::And, lhs, rhs) => {
+                (self.eval(lhs, depth)? != 0 && self.eval(rhs, depth)? != 0).into()
+            }
+            Expr::Binary(BinOp::Or, lhs, rhs) => {
+                (self.eval(lhs, depth)? != 0 || self.eval(rhs, depth)? != 0).into()
+            }
+            Expr::Binary(BinOp::Comma, lhs, rhs) => {
+                self.eval(lhs, depth)?;
+                self.eval(rhs, depth)?
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.eval(lhs, depth)?;
+                let rhs = self.eval(rhs, depth)?;
+                apply(*op, lhs, rhs)?
+            }
+            Expr::Cond(condition, then, otherwise) => {
+                if self.eval(condition, depth)? != 0 {
+                    self.eval(then, depth)?
+                } else {
+                    self.eval(otherwise, depth)?
+                }
+            }
+            Expr::Assign(name, op, value) => {
+                let mut value = self.eval(value, depth)?;
+                if let Some(op) = op {
+                    value = apply(*op, self.arith_var(name, depth)?, value)?;
+                }
+                self.vars.set(name, value.to_string());
+                value
+            }
+            Expr::Step {
+                name,
+                delta,
+                prefix,
+            } => {
+                let old = self.arith_var(name, depth)?;
+                let new = old.checked_add(*delta).ok_or_else(overflow)?;
+                self.vars.set(name, new.to_string());
+                if *prefix {
+                    new
+                } else {
+                    old
+                }
+            }
+        })
+    }
+
+    /// The value of a variable in an expression. Unset and empty variables are 0, and a
+    /// value that is not a number is evaluated as an expression itself.
+    fn arith_var(&mut self, name: &str, depth: usize) -> Result<i64> {
+        let value = self.vars.get(name).unwrap_or_default().trim().to_owned();
+        if value.is_empty() {
+            return Ok(0);
+        }
+        if let Some(value) = number(&value) {
+            return Ok(value);
+        }
+        if depth >= MAX_DEPTH {
+            bail!("expression recursion level exceeded");
+        }
+        self.arith_at(&value, depth + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(text: &str) -> Result<i64> {
+        Shell::new().unwrap().arith(text)
+    }
+
+    #[test]
+    fn follows_c_precedence() {
+        assert_eq!(eval("1 + 2 * 3").unwrap(), 7);
+        assert_eq!(eval("(1 + 2) * 3").unwrap(), 9);
+        assert_eq!(eval("1 << 2 + 1").unwrap(), 8);
+        assert_eq!(eval("1 | 2 ^ 3 & 4").unwrap(), 3);
+        assert_eq!(eval("1 < 2 == 1").unwrap(), 1);
+        assert_eq!(eval("-2 ** 2").unwrap(), 4);
+        assert_eq!(eval("!0 + ~0").unwrap(), 0);
+        assert_eq!(eval("1 ? 2 : 0 ? 3 : 4").unwrap(), 2);
+        assert_eq!(eval("0 || 2 && 3").unwrap(), 1);
+    }
+
+    #[test]
+    fn binary_operators_associate_left_except_power() {
+        assert_eq!(eval("10 - 3 - 2").unwrap(), 5);
+        assert_eq!(eval("64 / 4 / 2").unwrap(), 8);
+        assert_eq!(eval("2 ** 3 ** 2").unwrap(), 512);
+    }
+
+    #[test]
+    fn reads_constants_in_any_base() {
+        assert_eq!(
+            eval("0x1f + 010 + 2#101 + 36#z + 64#_").unwrap(),
+            31 + 8 + 5 + 35 + 63
+        );
+        assert!(eval("09").is_err());
+        assert!(eval("65#1").is_err());
+        assert_eq!(eval("").unwrap(), 0);
+        assert_eq!(eval("  ").unwrap(), 0);
+    }
+
+    #[test]
+    fn assigns_variables() {
+        let mut shell = Shell::new().unwrap();
+        shell.vars.set("x", "5");
+        assert_eq!(shell.arith("y = x += 2, x *= 3").unwrap(), 21);
+        assert_eq!(shell.vars.get("y"), Some("7"));
+        assert_eq!(shell.arith("x++ + ++x").unwrap(), 21 + 23);
+        assert_eq!(shell.arith("x--, --x").unwrap(), 21);
+        assert_eq!(shell.arith("z <<= 2").unwrap(), 0);
+        assert!(shell.arith("3 = 4").is_err());
+    }
+
+    #[test]
+    fn evaluates_variables_holding_expressions() {
+        let mut shell = Shell::new().unwrap();
+        shell.vars.set("a", "b + 1");
+        shell.vars.set("b", "2 * 3");
+        shell.vars.set("empty", "");
+        assert_eq!(shell.arith("a * 2 + empty + unset_var").unwrap(), 14);
+        shell.vars.set("loop", "loop");
+        let err = shell.arith("loop").unwrap_err();
+        assert!(err.to_string().contains("recursion level exceeded"));
+    }
+
+    #[test]
+    fn short_circuits() {
+        let mut shell = Shell::new().unwrap();
+        assert_eq!(
+            shell
+                .arith("0 && (x = 1), 1 || (y = 1), 1 ? 2 : (z = 1)")
+                .unwrap(),
+            2
+        );
+        assert_eq!(shell.vars.get("x"), None);
+        assert_eq!(shell.vars.get("y"), None);
+        assert_eq!(shell.vars.get("z"), None);
+        assert_eq!(shell.arith("0 && 1 / 0").unwrap(), 0);
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert_eq!(
+            eval("1 / 0").unwrap_err().to_string(),
+            "1 / 0: division by 0"
+        );
+        assert_eq!(
+            eval("5 % 0").unwrap_err().to_string(),
+            "5 % 0: division by 0"
+        );
+        assert!(eval("2 ** -1").is_err());
+        assert!(eval("1 << 64").is_err());
+        assert!(eval("9223372036854775807 + 1").is_err());
+        assert!(eval("-9223372036854775807 - 2").is_err());
+        assert_eq!(
+            eval("1 + * 2").unwrap_err().to_string(),
+            "1 + * 2: syntax error: operand expected (error token is \"*\")"
+        );
+        assert!(eval("(1 + 2").is_err());
+        assert!(eval("1 2").is_err());
+    }
+}
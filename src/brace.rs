@@ -0,0 +1,280 @@
+use anyhow::{bail, Result};
+
+use crate::parser::ast::{Word, WordPart};
+
+/// The most words a word may expand to, so that `{1..999999999999}` fails instead of
+/// exhausting memory.
+const MAX_WORDS: u64 = 1 << 20;
+
+/// A piece of a word: an unquoted literal character, which may take part in a brace
+/// expression, or any other part.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Item {
+    Char(char),
+    Part(WordPart),
+}
+
+/// Expands `{a,b}` lists and `{1..10..2}` or `{a..z}` ranges in the unquoted text of
+/// `word`, giving one word per alternative in order. Words without a valid brace
+/// expression are returned unchanged.
+pub fn expand(word: &Word) -> Result<Vec<Word>> {
+    let has_brace = word
+        .parts
+        .iter()
+        .any(|part| matches!(part, WordPart::Literal(text) if text.contains('{')));
+    if !has_brace {
+        return Ok(vec![word.clone()]);
+    }
+    let items: Vec<Item> = word
+        .parts
+        .iter()
+        .flat_map(|part| match part {
+            WordPart::Literal(text) => text.chars().map(Item::Char).collect(),
+            part => vec![Item::Part(part.clone())],
+        })
+        .collect();
+    Ok(expand_items(&items)?
+        .iter()
+        .map(|items| rebuild(items))
+        .collect())
+}
+
+fn expand_items(items: &[Item]) -> Result<Vec<Vec<Item>>> {
+    for open in 0..items.len() {
+        if items[open] != Item::Char('{') {
+            continue;
+        }
+        let Some((close, commas)) = find_close(items, open) else {
+            continue;
+        };
+        let alternatives: Vec<Vec<Item>> = if commas.is_empty() {
+            match range(&items[open + 1..close]) {
+                Some(range) => range?
+                    .into_iter()
+                    .map(|text| text.chars().map(Item::Char).collect())
+                    .collect(),
+                None => continue,
+            }
+        } else {
+            let mut bounds = vec![open];
+            bounds.extend(&commas);
+            bounds.push(close);
+            bounds
+                .windows(2)
+                .map(|pair| items[pair[0] + 1..pair[1]].to_vec())
+                .collect()
+        };
+        let mut words = Vec::new();
+        for alternative in alternatives {
+            let mut expanded = items[..open].to_vec();
+            expanded.extend(alternative);
+            expanded.extend_from_slice(&items[close + 1..]);
+            words.extend(expand_items(&expanded)?);
+            if words.len() as u64 > MAX_WORDS {
+                bail!("brace expansion: failed to allocate memory");
+            }
+        }
+        return Ok(words);
+    }
+    Ok(vec![items.to_vec()])
+}
+
+/// Finds the `}` matching the `{` at `open`, with the positions of the commas directly
+/// inside it.
+fn find_close(items: &[Item], open: usize) -> Option<(usize, Vec<usize>)> {
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    for (i, item) in items.iter().enumerate().skip(open) {
+        match item {
+            Item::Char('{') => depth += 1,
+            Item::Char('}') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((i, commas));
+                }
+            }
+            Item::Char(',') if depth == 1 => commas.push(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The words of a `start..end[..step]` sequence of integers or single letters, or `None`
+/// if `items` are not one.
+fn range(items: &[Item]) -> Option<Result<Vec<String>>> {
+    let text = items
+        .iter()
+        .map(|item| match item {
+            Item::Char(c) => Some(*c),
+            Item::Part(_) => None,
+        })
+        .collect::<Option<String>>()?;
+    let mut bounds = text.split("..");
+    let (start, end) = (bounds.next()?, bounds.next()?);
+    let step = match bounds.next() {
+        Some(step) => step.parse::<i64>().ok()?.unsigned_abs().max(1),
+        None => 1,
+    };
+    if bounds.next().is_some() {
+        return None;
+    }
+
+    if let (Ok(first), Ok(last)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        let padded = |s: &str| {
+            let digits = s.trim_start_matches('-');
+            digits.len() > 1 && digits.starts_with('0')
+        };
+        let width = if padded(start) || padded(end) {
+            start.len().max(end.len())
+        } else {
+            0
+        };
+        return Some(
+            sequence(first, last, step)
+                .map(|numbers| numbers.map(|n| format!("{n:0width$}")).collect()),
+        );
+    }
+
+    let letter = |s: &str| {
+        let mut chars = s.chars();
+        let c = chars.next().filter(char::is_ascii_alphabetic)?;
+        chars.next().is_none().then_some(c as i64)
+    };
+    let (first, last) = (letter(start)?, letter(end)?);
+    Some(
+        sequence(first, last, step)
+            .map(|letters| letters.map(|c| char::from(c as u8).to_string()).collect()),
+    )
+}
+
+/// Counts from `first` to `last`, up or down, by `step`.
+fn sequence(first: i64, last: i64, step: u64) -> Result<impl Iterator<Item = i64>> {
+    let count = (first.abs_diff(last) / step).saturating_add(1);
+    if count > MAX_WORDS {
+        bail!("brace expansion: failed to allocate memory");
+    }
+    let step = if first <= last {
+        i128::from(step)
+    } else {
+        -i128::from(step)
+    };
+    Ok((0..count).map(move |i| (i128::from(first) + i128::from(i) * step) as i64))
+}
+
+fn rebuild(items: &[Item]) -> Word {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    for item in items {
+        match item {
+            Item::Char(c) => literal.push(*c),
+            Item::Part(part) => {
+                if !literal.is_empty() {
+                    parts.push(WordPart::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(part.clone());
+            }
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(WordPart::Literal(literal));
+    }
+    Word { parts }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn literal(text: &str) -> Word {
+        Word {
+            parts: vec![WordPart::Literal(text.to_owned())],
+        }
+    }
+
+    fn expand_text(text: &str) -> Vec<String> {
+        expand(&literal(text))
+            .unwrap()
+            .iter()
+            .map(|word| word.unquoted())
+            .collect()
+    }
+
+    #[test]
+    fn expands_lists_in_order() {
+        assert_eq!(expand_text("src/{bin,lib}"), ["src/bin", "src/lib"]);
+        assert_eq!(expand_text("{a,b}{1,2}"), ["a1", "a2", "b1", "b2"]);
+        assert_eq!(expand_text("x{,y}"), ["x", "xy"]);
+        assert_eq!(expand_text("{a,{b,c}d}e"), ["ae", "bde", "cde"]);
+    }
+
+    #[test]
+    fn expands_ranges() {
+        assert_eq!(expand_text("{1..4}"), ["1", "2", "3", "4"]);
+        assert_eq!(expand_text("{3..-1..2}"), ["3", "1", "-1"]);
+        assert_eq!(expand_text("{08..11}"), ["08", "09", "10", "11"]);
+        assert_eq!(expand_text("{-1..01}"), ["-1", "00", "01"]);
+        assert_eq!(expand_text("{a..e..2}"), ["a", "c", "e"]);
+        assert_eq!(expand_text("{C..A}"), ["C", "B", "A"]);
+        assert_eq!(expand_text("{1..2..0}"), ["1", "2"]);
+        assert_eq!(
+            expand_text("{-9223372036854775808..9223372036854775807..9223372036854775807}"),
+            ["-9223372036854775808", "-1", "9223372036854775806"]
+        );
+    }
+
+    #[test]
+    fn refuses_ranges_too_large_to_hold() {
+        for text in [
+            "{1..999999999999}",
+            "{-9223372036854775808..9223372036854775807}",
+            "{1..1024}{1..1024}{1..2}",
+        ] {
+            let error = expand(&literal(text)).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "brace expansion: failed to allocate memory"
+            );
+        }
+    }
+
+    #[test]
+    fn leaves_invalid_expressions_alone() {
+        for text in [
+            "{a}",
+            "{}",
+            "{a,b",
+            "a}",
+            "{1..2..3..4}",
+            "{a..1}",
+            "{aa..b}",
+        ] {
+            assert_eq!(expand_text(text), [text]);
+        }
+        assert_eq!(expand_text("{x}{a,b}"), ["{x}a", "{x}b"]);
+    }
+
+    #[test]
+    fn quoted_and_expanded_parts_take_no_part() {
+        let word = Word {
+            parts: vec![
+                WordPart::Literal("{a".to_owned()),
+                WordPart::Quoted(",".to_owned()),
+                WordPart::Literal("b}".to_owned()),
+            ],
+        };
+        assert_eq!(expand(&word).unwrap(), std::slice::from_ref(&word));
+
+        let word = Word {
+            parts: vec![
+                WordPart::Literal("{".to_owned()),
+                WordPart::Quoted("x".to_owned()),
+                WordPart::Literal(",y}".to_owned()),
+            ],
+        };
+        let expanded = expand(&word).unwrap();
+        assert_eq!(expanded.len(), 2);
+        assert_eq!(expanded[0].parts, [WordPart::Quoted("x".to_owned())]);
+        assert_eq!(expanded[1], literal("y"));
+    }
+}
@@ -0,0 +1,360 @@
+use anyhow::{anyhow, bail, Result};
+use colored::Colorize;
+use std::{
+    collections::HashMap,
+    env,
+    io::{self, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use crate::{
+    compound::LoopControl,
+    options::Options,
+    parser::ast::{is_name, Function},
+    shell::Shell,
+    vars::quote,
+};
+
+#[derive(Debug)]
+pub enum Builtin {
+    History,
+    Cd,
+    Pwd,
+    Clear,
+    Exit,
+    ClearHistory,
+    Help,
+    Jobs,
+    Fg,
+    Bg,
+    Disown,
+    Wait,
+    Export,
+    Unset,
+    Set,
+    Env,
+    Let,
+    Shopt,
+    Break,
+    Continue,
+    Local,
+    Return,
+    Source,
+    Alias,
+    Unalias,
+    Fc,
+    Function(Function),
+    Other(String),
+}
+
+impl FromStr for Builtin {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "history" => Ok(Builtin::History),
+            "fc" => Ok(Builtin::Fc),
+            "cd" => Ok(Builtin::Cd),
+            "pwd" => Ok(Builtin::Pwd),
+            "clear" => Ok(Builtin::Clear),
+            "exit" => Ok(Builtin::Exit),
+            "help" => Ok(Builtin::Help),
+            "clearHistory" => Ok(Builtin::ClearHistory),
+            "jobs" => Ok(Builtin::Jobs),
+            "fg" => Ok(Builtin::Fg),
+            "bg" => Ok(Builtin::Bg),
+            "disown" => Ok(Builtin::Disown),
+            "wait" => Ok(Builtin::Wait),
+            "export" => Ok(Builtin::Export),
+            "unset" => Ok(Builtin::Unset),
+            "set" => Ok(Builtin::Set),
+            "env" => Ok(Builtin::Env),
+            "let" => Ok(Builtin::Let),
+            "shopt" => Ok(Builtin::Shopt),
+            "break" => Ok(Builtin::Break),
+            "continue" => Ok(Builtin::Continue),
+            "local" => Ok(Builtin::Local),
+            "return" => Ok(Builtin::Return),
+            "source" | "." => Ok(Builtin::Source),
+            "alias" => Ok(Builtin::Alias),
+            "unalias" => Ok(Builtin::Unalias),
+            _ => Ok(Builtin::Other(s.to_owned())),
+        }
+    }
+}
+
+impl Builtin {
+    /// Decides what runs for the command `words`: a builtin, else one of `functions`, else
+    /// an external program.
+    pub fn resolve(words: &[String], functions: &HashMap<String, Function>) -> Builtin {
+        match Builtin::from_str(&words[0]).unwrap() {
+            // `env NAME=value command` is the external program.
+            Builtin::Env if words.len() > 1 => Builtin::Other(words[0].clone()),
+            Builtin::Other(name) => match functions.get(&name) {
+                Some(function) => Builtin::Function(function.clone()),
+                None => Builtin::Other(name),
+            },
+            builtin => builtin,
+        }
+    }
+}
+
+/// Colors output only while standard output is a terminal, so that `pwd > file` or
+/// `history | grep` get plain text. Colors go back to the default when dropped.
+pub struct StdoutColors;
+
+impl StdoutColors {
+    pub fn new() -> Self {
+        colored::control::set_override(unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1);
+        Self
+    }
+}
+
+impl Drop for StdoutColors {
+    fn drop(&mut self) {
+        colored::control::unset_override();
+    }
+}
+
+impl Shell {
+    /// Runs a builtin and returns its exit status. `Builtin::Other` is not handled here.
+    pub fn run_builtin(
+        &mut self,
+        builtin: Builtin,
+        args: &[String],
+        out: &mut dyn Write,
+    ) -> Result<i32> {
+        // Builtins that run other commands leave colors to the builtins those run.
+        let _colors = (!matches!(
+            builtin,
+            Builtin::Function(_) | Builtin::Source | Builtin::Fc
+        ))
+        .then(StdoutColors::new);
+        match builtin {
+            Builtin::History => return self.builtin_history(args, out),
+            Builtin::Fc => return self.builtin_fc(args, out),
+            Builtin::Cd => {
+                let new_dir = args.first().map_or("/", |dir| dir.as_str());
+                if let Err(e) = env::set_current_dir(Path::new(new_dir)) {
+                    eprintln!("{}", e);
+                    return Ok(1);
+                }
+                self.current_path = new_dir.to_owned();
+                if let Some(pwd) = self.vars.get("PWD").map(str::to_owned) {
+                    self.vars.set("OLDPWD", pwd);
+                }
+                if let Ok(pwd) = env::current_dir() {
+                    self.vars.set("PWD", pwd.to_string_lossy());
+                }
+            }
+            Builtin::Pwd => writeln!(out, "{}", self.current_path.purple())?,
+            Builtin::Clear => self.rl.clear_screen()?,
+            Builtin::Exit => {
+                let code = match args.first() {
+                    Some(code) => code
+                        .parse::<i32>()
+                        .map_err(|_| anyhow!("exit: {code}: numeric argument required"))?,
+                    None => self.status,
+                };
+                self.exit_code = Some(code & 0xff);
+                return Ok(code & 0xff);
+            }
+            Builtin::ClearHistory => {
+                self.rl.clear_history()?;
+                self.history.clear();
+                self.rewrite_history(Vec::clear)?;
+                writeln!(out, "{}", "history cleared".purple())?;
+            }
+            Builtin::Help => print_help(out)?,
+            Builtin::Jobs => return self.builtin_jobs(args, out),
+            Builtin::Fg => return self.builtin_fg(args, out),
+            Builtin::Bg => return self.builtin_bg(args, out),
+            Builtin::Disown => return self.builtin_disown(args),
+            Builtin::Wait => return self.builtin_wait(args),
+            Builtin::Export => return self.builtin_export(args, out),
+            Builtin::Unset => {
+                let (functions, names) = match args.first().map(String::as_str) {
+                    Some("-f") => (true, &args[1..]),
+                    Some("-v") => (false, &args[1..]),
+                    _ => (false, args),
+                };
+                for name in names {
+                    if functions {
+                        self.functions.remove(name);
+                    } else {
+                        self.vars.unset(name);
+                    }
+                }
+            }
+            Builtin::Set => {
+                for (name, var) in self.vars.iter() {
+                    writeln!(out, "{}={}", name, quote(&var.value))?;
+                }
+            }
+            Builtin::Env => {
+                for (name, value) in self.vars.exported() {
+                    writeln!(out, "{name}={value}")?;
+                }
+            }
+            Builtin::Let => {
+                if args.is_empty() {
+                    bail!("let: expression expected");
+                }
+                let mut value = 0;
+                for arg in args {
+                    value = self.arith(arg)?;
+                }
+                return Ok((value == 0).into());
+            }
+            Builtin::Shopt => return self.builtin_shopt(args, out),
+            Builtin::Break => return self.builtin_loop_control(args, LoopControl::Break),
+            Builtin::Continue => return self.builtin_loop_control(args, LoopControl::Continue),
+            Builtin::Local => return self.builtin_local(args),
+            Builtin::Return => return self.builtin_return(args),
+            Builtin::Source => return self.builtin_source(args),
+            Builtin::Alias => return self.builtin_alias(args, out),
+            Builtin::Unalias => return self.builtin_unalias(args),
+            Builtin::Function(function) => return self.call_function(&function, args),
+            Builtin::Other(_) => unreachable!("external commands are not builtins"),
+        }
+        Ok(0)
+    }
+}
+
+impl Shell {
+    fn builtin_export(&mut self, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        let (exported, names) = match args.first().map(String::as_str) {
+            Some("-n") => (false, &args[1..]),
+            Some("-p") => (true, &[][..]),
+            _ => (true, args),
+        };
+        if names.is_empty() {
+            for (name, value) in self.vars.exported() {
+                writeln!(out, "export {}={}", name, quote(value))?;
+            }
+            return Ok(0);
+        }
+        let mut status = 0;
+        for arg in names {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            if !is_name(name) {
+                eprintln!("export: `{arg}`: not a valid identifier");
+                status = 1;
+                continue;
+            }
+            if let Some(value) = value {
+                self.vars.set(name, value);
+            }
+            self.vars.export(name, exported);
+        }
+        Ok(status)
+    }
+}
+
+impl Shell {
+    fn builtin_shopt(&mut self, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        let (value, names) = match args.first().map(String::as_str) {
+            Some("-s") => (Some(true), &args[1..]),
+            Some("-u") => (Some(false), &args[1..]),
+            _ => (None, args),
+        };
+        if names.is_empty() {
+            // Without names, `-s` and `-u` list the options that are on or off.
+            for name in Options::NAMES {
+                let on = *self.options.get_mut(name).unwrap();
+                if value.is_none_or(|value| value == on) {
+                    writeln!(out, "{name:<15} {}", if on { "on" } else { "off" })?;
+                }
+            }
+            return Ok(0);
+        }
+        let mut status = 0;
+        for name in names {
+            let Some(option) = self.options.get_mut(name) else {
+                eprintln!("shopt: {name}: invalid shell option name");
+                status = 1;
+                continue;
+            };
+            match value {
+                Some(value) => *option = value,
+                // Querying named options succeeds only if all of them are on.
+                None => {
+                    writeln!(out, "{name:<15} {}", if *option { "on" } else { "off" })?;
+                    if !*option {
+                        status = 1;
+                    }
+                }
+            }
+        }
+        Ok(status)
+    }
+}
+
+impl Shell {
+    /// Runs `break [N]` or `continue [N]`, which leave or restart the `N`th enclosing loop.
+    fn builtin_loop_control(
+        &mut self,
+        args: &[String],
+        control: fn(usize) -> LoopControl,
+    ) -> Result<i32> {
+        let name = match control(1) {
+            LoopControl::Break(_) => "break",
+            LoopControl::Continue(_) => "continue",
+        };
+        let levels = match args.first() {
+            Some(arg) => match arg.parse::<usize>() {
+                Ok(0) => {
+                    eprintln!("{name}: {arg}: loop count out of range");
+                    return Ok(1);
+                }
+                Ok(levels) => levels,
+                Err(_) => {
+                    eprintln!("{name}: {arg}: numeric argument required");
+                    return Ok(1);
+                }
+            },
+            None => 1,
+        };
+        if self.loop_depth == 0 {
+            eprintln!("{name}: only meaningful in a `for', `while', or `until' loop");
+            return Ok(0);
+        }
+        self.loop_control = Some(control(levels.min(self.loop_depth)));
+        Ok(0)
+    }
+}
+
+pub fn print_help(out: &mut dyn Write) -> io::Result<()> {
+    writeln!(
+        out,
+        "{} \n {}",
+        r#" these are the Builtin commands that you can use
+    - history [-v] [--since DATE] [--until DATE] [grep PATTERN] [N], history -d N: list or delete numbered history,
+      kept in $POTATO_HISTFILE or ~/.local/state/potato-shell/history
+    - fc [-l] [FIRST [LAST]], fc -s [OLD=NEW]: edit history in $EDITOR and run it, list it or run it again
+    - history export [--format json|csv] [FILE], history import FILE...: move history from bash, zsh, fish or exports
+    - !!, !N, !-N, !prefix, !?text?, ^old^new: rerun history, with words !$, !^, !:N and modifiers :h, :t, :r, :s/a/b/
+    - cd: change directory 
+    - pwd: see  dirctgoury you currently on
+    - clear: clear the screen
+    - exit [N]: exit the potato shell with status N
+    - clearhistory: clear you history
+    - help: see help againg :)
+    - jobs, fg %N, bg %N, disown %N, wait: manage jobs started with '&'
+    - export NAME=value, unset NAME, set, env: manage variables, used as $NAME
+    - let EXPR, ((EXPR)), $((EXPR)): integer arithmetic
+    - shopt [-s|-u] NAME: set options such as nullglob, failglob, dotglob, globstar, extglob, sharehistory
+    - if, while, until, for, case, break [N], continue [N]: run commands conditionally or in loops
+    - name() { ...; }, local NAME=value, return [N]: define functions, called with $1, $2, $@, $#
+    - source FILE, . FILE: run FILE in this shell; ~/.potatorc runs at startup
+    - alias NAME=value, unalias NAME: define shortcuts such as alias ll='ls -la'
+
+            "#
+        .purple(),
+        r#"use '|' for piping, "<" to read a file, ">" and ">>" to write or append, "2>&1" for errors, "<<EOF" for here-documents, "*", "?" and "[...]" for file names "#
+            .blue()
+    )
+}
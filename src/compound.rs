@@ -0,0 +1,213 @@
+use anyhow::Result;
+
+use crate::{
+    brace,
+    parser::ast::{CaseItem, CompoundCommand, Word},
+    shell::Shell,
+};
+
+/// A pending `break N` or `continue N`, counting the loops still to leave.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopControl {
+    Break(usize),
+    Continue(usize),
+}
+
+impl Shell {
+    /// Runs a compound command in the shell process and returns its status.
+    pub fn run_compound(&mut self, command: &CompoundCommand) -> Result<i32> {
+        match command {
+            CompoundCommand::Group(list) => {
+                self.run_list(list)?;
+                Ok(self.status)
+            }
+            CompoundCommand::Arith(expr) => {
+                let text = self.expand_word(expr)?;
+                Ok((self.arith(&text)? == 0).into())
+            }
+            CompoundCommand::If {
+                branches,
+                otherwise,
+            } => {
+                for (condition, body) in branches {
+                    self.run_list(condition)?;
+                    if self.stopped() {
+                        return Ok(self.status);
+                    }
+                    if self.status == 0 {
+                        self.run_list(body)?;
+                        return Ok(self.status);
+                    }
+                }
+                match otherwise {
+                    Some(body) => {
+                        self.run_list(body)?;
+                        Ok(self.status)
+                    }
+                    None => Ok(0),
+                }
+            }
+            CompoundCommand::Case { word, items } => self.run_case(word, items),
+            command => {
+                self.loop_depth += 1;
+                let status = self.run_loop(command);
+                self.loop_depth -= 1;
+                status
+            }
+        }
+    }
+
+    fn run_loop(&mut self, command: &CompoundCommand) -> Result<i32> {
+        let mut status = 0;
+        match command {
+            CompoundCommand::While {
+                until,
+                condition,
+                body,
+            } => loop {
+                self.run_list(condition)?;
+                if self.leave_loop() || (self.status == 0) == *until {
+                    break;
+                }
+                self.run_list(body)?;
+                status = self.status;
+                if self.leave_loop() {
+                    break;
+                }
+            },
+            CompoundCommand::For { name, words, body } => {
+                let values = match words {
+                    Some(words) => {
+                        let mut values = Vec::new();
+                        for word in words
+                            .iter()
+                            .map(brace::expand)
+                            .collect::<Result<Vec<_>>>()?
+                            .concat()
+                        {
+                            values.extend(self.expand_fields(&word)?);
+                        }
+                        values
+                    }
+                    None => self.positional.clone(),
+                };
+                for value in values {
+                    self.vars.set(name, value);
+                    self.run_list(body)?;
+                    status = self.status;
+                    if self.leave_loop() {
+                        break;
+                    }
+                }
+            }
+            CompoundCommand::ArithFor {
+                init,
+                condition,
+                step,
+                body,
+            } => {
+                self.arith_clause(init, 0)?;
+                while self.arith_clause(condition, 1)? != 0 {
+                    self.run_list(body)?;
+                    status = self.status;
+                    if self.leave_loop() {
+                        break;
+                    }
+                    self.arith_clause(step, 0)?;
+                }
+            }
+            _ => unreachable!("not a loop"),
+        }
+        if self.interrupted() {
+            return Ok(self.status);
+        }
+        Ok(status)
+    }
+
+    /// Evaluates one expression of `for ((...))`, which is `empty` when left out.
+    fn arith_clause(&mut self, expr: &Word, empty: i64) -> Result<i64> {
+        let text = self.expand_word(expr)?;
+        if text.trim().is_empty() {
+            return Ok(empty);
+        }
+        self.arith(&text)
+    }
+
+    /// Handles a pending `break` or `continue` after a loop iteration and tells whether the
+    /// loop must end.
+    fn leave_loop(&mut self) -> bool {
+        match self.loop_control.take() {
+            Some(LoopControl::Break(levels)) => {
+                if levels > 1 {
+                    self.loop_control = Some(LoopControl::Break(levels - 1));
+                }
+                true
+            }
+            Some(LoopControl::Continue(levels)) if levels > 1 => {
+                self.loop_control = Some(LoopControl::Continue(levels - 1));
+                true
+            }
+            Some(LoopControl::Continue(_)) => false,
+            None => self.stopped(),
+        }
+    }
+
+    fn run_case(&mut self, word: &Word, items: &[CaseItem]) -> Result<i32> {
+        let word = self.tilde(word, false);
+        let text = self.expand_word(&word)?;
+        for item in items {
+            for pattern in &item.patterns {
+                if self.expand_pattern(pattern)?.matches(&text) {
+                    self.status = 0;
+                    self.run_list(&item.body)?;
+                    return Ok(self.status);
+                }
+            }
+        }
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(commands: &str) -> Shell {
+        let mut shell = Shell::new().unwrap();
+        shell.handel_command(commands).unwrap();
+        shell
+    }
+
+    #[test]
+    fn break_and_continue_leave_or_restart_nested_loops() {
+        let shell = run(
+            "out=; for i in 1 2 3; do for j in a b c; do \
+             if [ $j = b ]; then continue 2; fi; out=\"$out$i$j \"; done; out=\"${out}never\"; done",
+        );
+        assert_eq!(shell.vars.get("out"), Some("1a 2a 3a "));
+
+        let shell = run(
+            "n=0; while true; do n=$((n+1)); until false; do break 2; done; n=9; done; \
+             for i in 1 2; do for j in 1 2; do break; done; done",
+        );
+        assert_eq!(shell.vars.get("n"), Some("1"));
+        assert_eq!(shell.vars.get("i"), Some("2"));
+
+        let shell = run("for i in 1 2; do break 5; done; for j in 1 2; do continue 9; done");
+        assert_eq!(shell.vars.get("i"), Some("1"));
+        assert_eq!(shell.vars.get("j"), Some("2"));
+        assert!(shell.loop_control.is_none());
+    }
+
+    #[test]
+    fn bad_loop_counts_fail() {
+        for commands in [
+            "for i in 1; do continue 0; done",
+            "for i in 1; do break x; done",
+        ] {
+            let shell = run(commands);
+            assert_eq!(shell.status, 1, "{commands}");
+            assert!(shell.loop_control.is_none());
+        }
+    }
+}
@@ -0,0 +1,600 @@
+use anyhow::{anyhow, Result};
+use colored::Colorize;
+use libc::{pid_t, SIGINT, SIGPIPE};
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    os::{
+        fd::{AsRawFd, OwnedFd},
+        unix::process::{CommandExt, ExitStatusExt},
+    },
+    process::{Command, ExitStatus, Stdio},
+};
+
+use crate::{
+    brace,
+    builtins::Builtin,
+    jobs::{exit_code, Job, JobState, Process},
+    parser::{
+        self,
+        ast::{self, AndOr, Assignment, Connector, List, Pipeline, SimpleCommand, Word},
+    },
+    redirect::Redirections,
+    shell::Shell,
+    signals,
+};
+
+impl Shell {
+    pub fn handel_command(&mut self, input: &str) -> Result<()> {
+        let list = parser::parse_with_aliases(input, &self.aliases)
+            .map_err(|err| anyhow!(err.render(input)))?;
+        self.loop_control = None;
+        self.run_list(&list)
+    }
+
+    pub fn run_list(&mut self, list: &List) -> Result<()> {
+        for and_or in &list.items {
+            self.run_and_or(and_or)?;
+            if self.stopped() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the rest of the current list must be skipped, because the shell is exiting,
+    /// the user pressed Ctrl-C, a function is returning or a `break` or `continue` is pending.
+    pub fn stopped(&mut self) -> bool {
+        // Ctrl-C while the shell itself is busy, as in `while true; do :; done`, only sets
+        // a flag that is checked here.
+        if self.interactive && signals::take_interrupt() {
+            self.status = 128 + SIGINT;
+            eprintln!();
+        }
+        self.exit_code.is_some()
+            || self.interrupted()
+            || self.loop_control.is_some()
+            || self.returning
+    }
+
+    /// Whether the last foreground job was killed by Ctrl-C, which abandons the rest of
+    /// the command line.
+    pub fn interrupted(&self) -> bool {
+        self.interactive && self.status == 128 + SIGINT
+    }
+
+    fn run_and_or(&mut self, and_or: &AndOr) -> Result<()> {
+        if and_or.background {
+            return self.run_background(and_or);
+        }
+        self.run_pipeline(&and_or.first)?;
+        for (connector, pipeline) in &and_or.rest {
+            if self.stopped() {
+                break;
+            }
+            let run = match connector {
+                Connector::And => self.status == 0,
+                Connector::Or => self.status != 0,
+            };
+            if run {
+                self.run_pipeline(pipeline)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `and_or` in a forked copy of the shell and adds it to the job table.
+    fn run_background(&mut self, and_or: &AndOr) -> Result<()> {
+        let _ = io::stdout().flush();
+        let pid = match unsafe { libc::fork() } {
+            -1 => return Err(io::Error::last_os_error().into()),
+            0 => {
+                if self.interactive {
+                    unsafe { libc::setpgid(0, 0) };
+                } else if let Ok(null) = File::open("/dev/null") {
+                    unsafe { libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO) };
+                }
+                signals::reset();
+                self.interactive = false;
+                self.jobs.clear();
+                let result = self.run_and_or(&AndOr {
+                    background: false,
+                    ..and_or.clone()
+                });
+                self.exit_forked(result)
+            }
+            pid => pid,
+        };
+        if self.interactive {
+            unsafe { libc::setpgid(pid, pid) };
+        }
+        let command = format!("{} &", and_or.text());
+        let process = Process {
+            pid,
+            name: command.clone(),
+            status: None,
+            stopped: false,
+        };
+        let id = self.add_job(Job::new(pid, command, vec![process]));
+        if self.interactive {
+            eprintln!("[{id}] {pid}");
+        }
+        self.last_background = Some(pid);
+        self.status = 0;
+        Ok(())
+    }
+
+    fn run_pipeline(&mut self, pipeline: &Pipeline) -> Result<()> {
+        self.substitution_status = None;
+        let mut expanded = None;
+        if let [command] = &pipeline.commands[..] {
+            let status = match command {
+                ast::Command::Simple(command) => {
+                    let words = self.expand_words(&command.words)?;
+                    if self.interactive && self.substitution_status == Some(128 + SIGINT) {
+                        eprintln!();
+                        return Ok(());
+                    }
+                    let status = self.run_in_shell(command, &words)?;
+                    expanded = Some(words);
+                    status
+                }
+                ast::Command::Compound(command, redirects) => {
+                    let redirects = self.open_redirects(redirects)?;
+                    let _saved = redirects.apply_saved()?;
+                    Some(self.run_compound(command)?)
+                }
+                ast::Command::Function(function) => {
+                    self.define_function(function);
+                    Some(0)
+                }
+            };
+            if let Some(status) = status {
+                self.status = status;
+                self.pipe_status = vec![status];
+                return Ok(());
+            }
+        }
+
+        let mut job = Job::new(0, pipeline.text.clone(), Vec::new());
+        let spawned = self.spawn_pipeline(pipeline, expanded, &mut job);
+        let torn_down = !matches!(spawned, Ok(true));
+        if torn_down {
+            for process in &job.processes {
+                unsafe { libc::kill(process.pid, libc::SIGKILL) };
+            }
+            while job.state() != JobState::Done {
+                job.wait(true)?;
+            }
+            // The first stage may have taken the terminal before a later one failed.
+            if self.interactive {
+                unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, self.pgid) };
+            }
+        } else {
+            self.wait_foreground(&mut job)?;
+        }
+
+        let mut statuses = Vec::new();
+        for process in &job.processes {
+            statuses.push(match process.status {
+                Some(status) => {
+                    if let Some(signal) = status
+                        .signal()
+                        .filter(|signal| !torn_down && ![SIGPIPE, SIGINT].contains(signal))
+                    {
+                        eprintln!(
+                            "{}",
+                            format!("{}: terminated by signal {signal}", process.name).red()
+                        );
+                    }
+                    exit_code(status)
+                }
+                None => 128 + libc::SIGTSTP,
+            });
+        }
+
+        match spawned {
+            Err(err) => {
+                statuses.push(1);
+                self.status = 1;
+                self.pipe_status = statuses;
+                return Err(err);
+            }
+            Ok(false) => {
+                statuses.push(127);
+                self.status = 127;
+            }
+            Ok(true) => {
+                self.status = self.finish_foreground(job);
+                if self.interrupted() {
+                    eprintln!();
+                }
+            }
+        }
+        self.pipe_status = statuses;
+        Ok(())
+    }
+
+    /// Runs a lone builtin or redirection-only command in the shell process itself, so it
+    /// can change the shell's state. Returns `None` for external commands.
+    fn run_in_shell(&mut self, command: &SimpleCommand, words: &[String]) -> Result<Option<i32>> {
+        let builtin = match words.first() {
+            Some(_) => match Builtin::resolve(words, &self.functions) {
+                Builtin::Other(_) => return Ok(None),
+                builtin => Some(builtin),
+            },
+            None => None,
+        };
+        let assignments = self.expand_assignments(&command.assignments)?;
+        let redirects = self.open_redirects(&command.redirects)?;
+        let Some(builtin) = builtin else {
+            for (name, value) in assignments {
+                self.vars.set(&name, value);
+            }
+            return Ok(Some(self.substitution_status.unwrap_or(0)));
+        };
+
+        // Assignments before a builtin are exported for as long as it runs, as they would
+        // be to an external command.
+        let previous: Vec<_> = assignments
+            .into_iter()
+            .map(|(name, value)| {
+                let previous = self.vars.var(&name).cloned();
+                self.vars.set(&name, value);
+                self.vars.export(&name, true);
+                (name, previous)
+            })
+            .collect();
+        let _saved = redirects.apply_saved()?;
+        let status = self.run_builtin(builtin, &words[1..], &mut io::stdout().lock());
+        for (name, previous) in previous.into_iter().rev() {
+            self.vars.restore(&name, previous);
+        }
+        Ok(Some(status?))
+    }
+
+    /// Starts every stage of `pipeline` as a process of `job`, using `expanded` as the words
+    /// of the first stage if they were already expanded. Returns `Ok(false)` when a stage
+    /// failed to start and the pipeline must be torn down.
+    fn spawn_pipeline(
+        &mut self,
+        pipeline: &Pipeline,
+        mut expanded: Option<Vec<String>>,
+        job: &mut Job,
+    ) -> Result<bool> {
+        let mut commands = pipeline.commands.iter().peekable();
+        let mut previous_stdout: Option<OwnedFd> = None;
+
+        while let Some(command) = commands.next() {
+            let stdin = previous_stdout.take();
+            let (mut next_stdin, stdout) = if commands.peek().is_some() {
+                let (reader, writer) = io::pipe()?;
+                (Some(OwnedFd::from(reader)), Some(OwnedFd::from(writer)))
+            } else {
+                (None, None)
+            };
+
+            let (name, pid) = match command {
+                ast::Command::Simple(command) => {
+                    let words = match expanded.take() {
+                        Some(words) => words,
+                        None => self.expand_words(&command.words)?,
+                    };
+                    let assignments = self.expand_assignments(&command.assignments)?;
+                    let redirects = self.open_redirects(&command.redirects)?;
+                    let name = words.first().cloned().unwrap_or_default();
+                    let builtin = words
+                        .first()
+                        .map(|_| Builtin::resolve(&words, &self.functions));
+                    let pid = match builtin {
+                        Some(Builtin::Other(command)) => {
+                            let mut process = Command::new(&command);
+                            process
+                                .args(&words[1..])
+                                .env_clear()
+                                .envs(self.vars.exported())
+                                .envs(assignments)
+                                .stdin(stdin.map_or(Stdio::inherit(), Stdio::from))
+                                .stdout(stdout.map_or(Stdio::inherit(), Stdio::from));
+                            let interactive = self.interactive;
+                            if interactive {
+                                process.process_group(job.pgid);
+                            }
+                            unsafe {
+                                process.pre_exec(move || {
+                                    if interactive {
+                                        take_terminal();
+                                    }
+                                    signals::reset();
+                                    redirects.apply()
+                                });
+                            }
+
+                            match process.spawn() {
+                                Ok(child) => child.id() as pid_t,
+                                Err(e) => {
+                                    eprintln!(
+                                        "{}{}: {}",
+                                        "command failed to start : ".red(),
+                                        command,
+                                        e
+                                    );
+                                    return Ok(false);
+                                }
+                            }
+                        }
+                        builtin => {
+                            self.fork_stage(job.pgid, &mut next_stdin, stdin, stdout, |shell| {
+                                for (name, value) in assignments {
+                                    shell.vars.set(&name, value);
+                                    shell.vars.export(&name, true);
+                                }
+                                shell.run_stage(builtin, &words, redirects)
+                            })?
+                        }
+                    };
+                    (name, pid)
+                }
+                ast::Command::Compound(compound, redirects) => {
+                    let pid =
+                        self.fork_stage(job.pgid, &mut next_stdin, stdin, stdout, |shell| {
+                            shell.open_redirects(redirects)?.apply()?;
+                            shell.status = shell.run_compound(compound)?;
+                            Ok(())
+                        })?;
+                    (command.name(), pid)
+                }
+                ast::Command::Function(function) => {
+                    let pid =
+                        self.fork_stage(job.pgid, &mut next_stdin, stdin, stdout, |shell| {
+                            shell.define_function(function);
+                            shell.status = 0;
+                            Ok(())
+                        })?;
+                    (command.name(), pid)
+                }
+            };
+
+            let first = job.pgid == 0;
+            if first {
+                job.pgid = pid;
+            }
+            if self.interactive {
+                unsafe { libc::setpgid(pid, job.pgid) };
+                // The terminal goes to the job before the next stage starts, so that none
+                // of them reads from it while still in the background and gets stopped.
+                if first {
+                    unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, job.pgid) };
+                }
+            }
+            job.processes.push(Process {
+                pid,
+                name,
+                status: None,
+                stopped: false,
+            });
+            previous_stdout = next_stdin;
+        }
+        Ok(true)
+    }
+
+    /// Forks a copy of the shell for a pipeline stage in process group `pgid`, with `stdin`
+    /// and `stdout` as its standard input and output, and runs `run` in it. The child closes
+    /// `next_stdin`, the read end of the pipe meant for the next stage.
+    fn fork_stage(
+        &mut self,
+        pgid: pid_t,
+        next_stdin: &mut Option<OwnedFd>,
+        stdin: Option<OwnedFd>,
+        stdout: Option<OwnedFd>,
+        run: impl FnOnce(&mut Self) -> Result<()>,
+    ) -> Result<pid_t> {
+        let _ = io::stdout().flush();
+        match unsafe { libc::fork() } {
+            -1 => Err(io::Error::last_os_error().into()),
+            0 => {
+                drop(next_stdin.take());
+                if self.interactive {
+                    unsafe { libc::setpgid(0, pgid) };
+                    take_terminal();
+                }
+                signals::reset();
+                self.interactive = false;
+                for (fd, target) in [(stdin, 0), (stdout, 1)] {
+                    if let Some(fd) = fd {
+                        unsafe { libc::dup2(fd.as_raw_fd(), target) };
+                    }
+                }
+                let result = run(self);
+                self.exit_forked(result)
+            }
+            pid => Ok(pid),
+        }
+    }
+
+    /// Runs a builtin or redirection-only pipeline stage in a forked child.
+    fn run_stage(
+        &mut self,
+        builtin: Option<Builtin>,
+        words: &[String],
+        redirects: Redirections,
+    ) -> Result<()> {
+        redirects.apply()?;
+        self.status = match builtin {
+            Some(builtin) => self.run_builtin(builtin, &words[1..], &mut io::stdout().lock())?,
+            None => 0,
+        };
+        Ok(())
+    }
+
+    /// Runs `list` in a forked copy of the shell for `$(...)` and returns what it wrote to
+    /// stdout without trailing newlines.
+    pub fn substitute(&mut self, list: &List) -> Result<String> {
+        let (mut reader, writer) = io::pipe()?;
+        let _ = io::stdout().flush();
+        let pid = match unsafe { libc::fork() } {
+            -1 => return Err(io::Error::last_os_error().into()),
+            0 => {
+                drop(reader);
+                unsafe { libc::dup2(writer.as_raw_fd(), libc::STDOUT_FILENO) };
+                drop(writer);
+                signals::reset();
+                self.interactive = false;
+                self.jobs.clear();
+                let result = self.run_list(list);
+                self.exit_forked(result)
+            }
+            pid => pid,
+        };
+        drop(writer);
+        let mut output = Vec::new();
+        let read = reader.read_to_end(&mut output);
+        let mut status = 0;
+        while unsafe { libc::waitpid(pid, &mut status, 0) } == -1 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err.into());
+            }
+        }
+        read?;
+        self.status = exit_code(ExitStatus::from_raw(status));
+        self.substitution_status = Some(self.status);
+
+        while output.last() == Some(&b'\n') {
+            output.pop();
+        }
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+
+    /// Ends a forked child of the shell with the status of what it ran.
+    fn exit_forked(&mut self, result: Result<()>) -> ! {
+        let status = match result {
+            Ok(()) => self.exit_code.unwrap_or(self.status),
+            Err(err) => {
+                eprintln!("{}", err.to_string().red());
+                1
+            }
+        };
+        let _ = io::stdout().flush();
+        unsafe { libc::_exit(status) }
+    }
+
+    /// Expands the words of a simple command, starting with brace expansion. Arguments of
+    /// declaration builtins that look like assignments are expanded as assignments and not
+    /// split, as in `export PATH=$PATH:$dir` or `local dir=$1`.
+    fn expand_words(&mut self, words: &[Word]) -> Result<Vec<String>> {
+        let mut fields = Vec::new();
+        for word in words
+            .iter()
+            .map(brace::expand)
+            .collect::<Result<Vec<_>>>()?
+            .concat()
+        {
+            match word.as_assignment() {
+                Some(assignment)
+                    if fields
+                        .first()
+                        .is_some_and(|name| name == "export" || name == "local") =>
+                {
+                    let value = self.expand_value(&assignment.value)?;
+                    fields.push(format!("{}={}", assignment.name, value));
+                }
+                _ => fields.extend(self.expand_fields(&word)?),
+            }
+        }
+        Ok(fields)
+    }
+
+    fn expand_assignments(&mut self, assignments: &[Assignment]) -> Result<Vec<(String, String)>> {
+        assignments
+            .iter()
+            .map(|assignment| {
+                Ok((
+                    assignment.name.clone(),
+                    self.expand_value(&assignment.value)?,
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Hands the terminal to the process group of a foreground stage from inside it, as the
+/// shell may not have done so yet. Safe to call between `fork` and `exec`, before
+/// `SIGTTOU` is no longer ignored.
+fn take_terminal() {
+    unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp()) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+
+    /// Runs `command` in an interactive shell forked onto a new pseudo-terminal, and tells
+    /// whether the shell had the terminal back afterwards.
+    fn keeps_terminal(command: &str) -> bool {
+        let (mut master, mut slave) = (0, 0);
+        let opened = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                ptr::null_mut(),
+                ptr::null(),
+                ptr::null(),
+            )
+        };
+        assert_eq!(opened, 0, "{}", io::Error::last_os_error());
+        match unsafe { libc::fork() } {
+            -1 => panic!("{}", io::Error::last_os_error()),
+            0 => {
+                unsafe {
+                    libc::setsid();
+                    libc::ioctl(slave, libc::TIOCSCTTY, 0);
+                    for fd in 0..3 {
+                        libc::dup2(slave, fd);
+                    }
+                }
+                let mut shell = Shell::new().unwrap();
+                shell.init_job_control();
+                let _ = shell.handel_command(command);
+                let kept = unsafe { libc::tcgetpgrp(libc::STDIN_FILENO) == libc::getpgrp() };
+                unsafe { libc::_exit(i32::from(!kept)) };
+            }
+            pid => {
+                unsafe { libc::close(slave) };
+                let mut status = 0;
+                unsafe { libc::waitpid(pid, &mut status, 0) };
+                unsafe { libc::close(master) };
+                libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0
+            }
+        }
+    }
+
+    #[test]
+    fn exports_assignments_before_builtins_while_they_run() {
+        let mut shell = Shell::new().unwrap();
+        shell.vars.unset("POTATO_Z");
+        shell
+            .handel_command("f() { seen=$(env); }; POTATO_Z=3 f")
+            .unwrap();
+        assert!(shell.vars.get("seen").unwrap().contains("POTATO_Z=3"));
+        assert_eq!(shell.vars.get("POTATO_Z"), None);
+    }
+
+    #[test]
+    fn lists_jobs_from_a_pipeline_stage() {
+        let mut shell = Shell::new().unwrap();
+        shell.handel_command("sleep 0.2 &").unwrap();
+        shell
+            .handel_command("jobs | cat >/dev/null; status=${PIPESTATUS[0]}")
+            .unwrap();
+        assert_eq!(shell.vars.get("status"), Some("0"));
+    }
+
+    #[test]
+    fn takes_the_terminal_back_from_a_pipeline() {
+        assert!(keeps_terminal("true | true"));
+        assert!(keeps_terminal("potato-no-such-command"));
+        assert!(keeps_terminal("true | potato-no-such-command | true"));
+    }
+}
@@ -0,0 +1,661 @@
+use anyhow::{bail, Result};
+use std::ffi::{CStr, CString};
+
+use crate::{
+    glob,
+    parser::ast::{is_name, ParamExp, ParamOp, ReplaceMode, TestKind, Word, WordPart},
+    pattern::{self, Pattern},
+    shell::Shell,
+};
+
+/// A field being built. `pattern` is the same text with quoted characters escaped, so
+/// only unquoted ones are special when it is used for pathname expansion.
+#[derive(Default)]
+struct Field {
+    text: String,
+    pattern: String,
+}
+
+/// Fields produced while expanding a word.
+struct Fields {
+    fields: Vec<Field>,
+    current: Field,
+    /// The current field exists even if empty, e.g. because of `""`.
+    active: bool,
+    ifs: Option<String>,
+}
+
+impl Fields {
+    fn new(ifs: Option<String>) -> Self {
+        Self {
+            fields: Vec::new(),
+            current: Field::default(),
+            active: false,
+            ifs,
+        }
+    }
+
+    /// Adds quoted text.
+    fn push_str(&mut self, text: &str) {
+        self.current.text.push_str(text);
+        self.current.pattern.push_str(&pattern::escape(text));
+        self.active = true;
+    }
+
+    /// Adds unquoted text that is not split, such as a literal part of the word.
+    fn push_unquoted(&mut self, text: &str) {
+        self.current.text.push_str(text);
+        self.current.pattern.push_str(text);
+        self.active = true;
+    }
+
+    /// Adds the result of an unquoted expansion, splitting it on `$IFS`.
+    fn push_split(&mut self, text: &str) {
+        let Some(ifs) = self.ifs.take() else {
+            return self.push_unquoted(text);
+        };
+        for c in text.chars() {
+            if !ifs.contains(c) {
+                self.current.text.push(c);
+                self.current.pattern.push(c);
+                self.active = true;
+            } else if !c.is_whitespace() || self.active {
+                self.fields.push(std::mem::take(&mut self.current));
+                self.active = false;
+            }
+        }
+        self.ifs = Some(ifs);
+    }
+
+    /// Ends the current field, as between the positional parameters of `$@`. Unquoted
+    /// empty fields are dropped unless `keep_empty` is set.
+    fn end_field(&mut self, keep_empty: bool) {
+        if self.active || keep_empty {
+            self.fields.push(std::mem::take(&mut self.current));
+        }
+        self.active = false;
+    }
+
+    fn finish(mut self) -> Vec<Field> {
+        if self.active {
+            self.fields.push(self.current);
+        }
+        self.fields
+    }
+}
+
+impl Shell {
+    /// Expands a word into one string, without field splitting or pathname expansion.
+    pub fn expand_word(&mut self, word: &Word) -> Result<String> {
+        let mut fields = Fields::new(None);
+        self.expand_parts(&word.parts, false, &mut fields)?;
+        Ok(fields.current.text)
+    }
+
+    /// Expands the value of an assignment, where a `~` after the `=` or after any `:` is
+    /// expanded, as in `PATH=~/bin:~/.local/bin`.
+    pub fn expand_value(&mut self, word: &Word) -> Result<String> {
+        let word = self.tilde(word, true);
+        self.expand_word(&word)
+    }
+
+    /// Replaces an unquoted `~`, `~user`, `~+` or `~-` at the start of `word` with the
+    /// directory it names. Prefixes naming nothing are left alone.
+    pub fn tilde(&self, word: &Word, assignment: bool) -> Word {
+        let mut parts = Vec::new();
+        for (index, part) in word.parts.iter().enumerate() {
+            let WordPart::Literal(text) = part else {
+                parts.push(part.clone());
+                continue;
+            };
+            let last = index + 1 == word.parts.len();
+            let mut literal = String::new();
+            let mut rest = text.as_str();
+            let mut at_start = index == 0;
+            while !rest.is_empty() {
+                if at_start && rest.starts_with('~') {
+                    let end = rest
+                        .find(|c| c == '/' || (assignment && c == ':'))
+                        .unwrap_or(rest.len());
+                    // A prefix running into a quoted part is partly quoted.
+                    let dir = (end < rest.len() || last)
+                        .then(|| self.tilde_dir(&rest[1..end]))
+                        .flatten();
+                    if let Some(dir) = dir {
+                        if !literal.is_empty() {
+                            parts.push(WordPart::Literal(std::mem::take(&mut literal)));
+                        }
+                        parts.push(WordPart::Quoted(dir));
+                        rest = &rest[end..];
+                        at_start = false;
+                        continue;
+                    }
+                }
+                let c = rest.chars().next().unwrap();
+                literal.push(c);
+                rest = &rest[c.len_utf8()..];
+                at_start = assignment && c == ':';
+            }
+            if !literal.is_empty() {
+                parts.push(WordPart::Literal(literal));
+            }
+        }
+        Word { parts }
+    }
+
+    fn tilde_dir(&self, prefix: &str) -> Option<String> {
+        match prefix {
+            "" => self.vars.get("HOME").map(str::to_owned).or_else(|| {
+                let passwd = unsafe { libc::getpwuid(libc::getuid()) };
+                home_dir(passwd)
+            }),
+            "+" => self.vars.get("PWD").map(str::to_owned),
+            "-" => self.vars.get("OLDPWD").map(str::to_owned),
+            user => {
+                let user = CString::new(user).ok()?;
+                let passwd = unsafe { libc::getpwnam(user.as_ptr()) };
+                home_dir(passwd)
+            }
+        }
+    }
+
+    /// Expands a word into fields, splitting unquoted expansions on `$IFS` and replacing
+    /// fields with unquoted wildcards by the paths they match.
+    pub fn expand_fields(&mut self, word: &Word) -> Result<Vec<String>> {
+        let word = self.tilde(word, false);
+        let ifs = self.vars.get("IFS").unwrap_or(" \t\n").to_owned();
+        let mut fields = Fields::new(Some(ifs));
+        self.expand_parts(&word.parts, false, &mut fields)?;
+
+        let mut words = Vec::new();
+        for field in fields.finish() {
+            if !pattern::has_wildcards(&field.pattern, self.options.extglob) {
+                words.push(field.text);
+                continue;
+            }
+            let paths = glob::glob(&field.pattern, &self.options);
+            if !paths.is_empty() {
+                words.extend(paths);
+            } else if self.options.failglob {
+                bail!("no match: {}", field.text);
+            } else if !self.options.nullglob {
+                words.push(field.text);
+            }
+        }
+        Ok(words)
+    }
+
+    fn expand_parts(
+        &mut self,
+        parts: &[WordPart],
+        quoted: bool,
+        fields: &mut Fields,
+    ) -> Result<()> {
+        for part in parts {
+            match part {
+                WordPart::Literal(text) if !quoted => fields.push_unquoted(text),
+                WordPart::Literal(text) | WordPart::Quoted(text) => fields.push_str(text),
+                WordPart::DoubleQuoted(parts) => {
+                    // `"$@"` without positional parameters gives no field at all.
+                    if !matches!(&parts[..], [WordPart::Param(param)] if param.is_all_args()) {
+                        fields.active = true;
+                    }
+                    self.expand_parts(parts, true, fields)?;
+                }
+                WordPart::Param(param) => self.expand_param(param, quoted, fields)?,
+                WordPart::Command(list) => {
+                    let output = self.substitute(list)?;
+                    if quoted {
+                        fields.push_str(&output);
+                    } else {
+                        fields.push_split(&output);
+                    }
+                }
+                WordPart::Arith(expr) => {
+                    let text = self.expand_word(expr)?;
+                    let value = self.arith(&text)?.to_string();
+                    if quoted {
+                        fields.push_str(&value);
+                    } else {
+                        fields.push_split(&value);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn expand_param(&mut self, param: &ParamExp, quoted: bool, fields: &mut Fields) -> Result<()> {
+        let name = &param.name;
+        // Outside of assignments `$@`, and `$*` when unquoted, give one field per parameter,
+        // and so do `${name[@]}` and `${name[*]}` per element.
+        let unquoted_all =
+            !quoted && param.op == ParamOp::None && (name == "*" || name.ends_with("[*]"));
+        if fields.ifs.is_some() && (param.is_all_args() || unquoted_all) {
+            let values = match name.split_once('[') {
+                Some((array, _)) => self.elements(array).unwrap_or_default(),
+                None => self.positional.clone(),
+            };
+            for (i, arg) in values.iter().enumerate() {
+                if i > 0 {
+                    fields.end_field(quoted);
+                }
+                if quoted {
+                    fields.push_str(arg);
+                } else {
+                    fields.push_split(arg);
+                }
+            }
+            return Ok(());
+        }
+        let value = self.param(name);
+        let text = match &param.op {
+            ParamOp::None => value.unwrap_or_default(),
+            ParamOp::Length if name.ends_with("[@]") || name.ends_with("[*]") => {
+                let (array, _) = name.split_once('[').unwrap_or_default();
+                self.elements(array)
+                    .map_or(0, |elements| elements.len())
+                    .to_string()
+            }
+            ParamOp::Length => value.map_or(0, |value| value.chars().count()).to_string(),
+            ParamOp::Test { kind, colon, word } => {
+                let set = value
+                    .as_ref()
+                    .is_some_and(|value| !colon || !value.is_empty());
+                match (kind, set) {
+                    (TestKind::Default, false) | (TestKind::Alternative, true) => {
+                        // The word of an unquoted expansion is split like the expansion itself,
+                        // so `${x:-a b}` gives two fields.
+                        for part in &word.parts {
+                            match part {
+                                WordPart::Literal(text) if !quoted => fields.push_split(text),
+                                part => {
+                                    self.expand_parts(std::slice::from_ref(part), quoted, fields)?
+                                }
+                            }
+                        }
+                        return Ok(());
+                    }
+                    (TestKind::Alternative, false) => String::new(),
+                    (_, true) => value.unwrap_or_default(),
+                    (TestKind::Assign, false) => {
+                        if !is_name(name) {
+                            bail!("${name}: cannot assign in this way");
+                        }
+                        let value = self.expand_word(word)?;
+                        self.vars.set(name, value.clone());
+                        value
+                    }
+                    (TestKind::Error, false) => {
+                        let message = self.expand_word(word)?;
+                        if message.is_empty() {
+                            bail!("{name}: parameter null or not set");
+                        }
+                        bail!("{name}: {message}");
+                    }
+                }
+            }
+            ParamOp::RemovePrefix { longest, pattern } => {
+                let pattern = self.expand_pattern(pattern)?;
+                let value = value.unwrap_or_default();
+                let ends = boundaries(&value, *longest);
+                match ends.into_iter().find(|end| pattern.matches(&value[..*end])) {
+                    Some(end) => value[end..].to_owned(),
+                    None => value,
+                }
+            }
+            ParamOp::RemoveSuffix { longest, pattern } => {
+                let pattern = self.expand_pattern(pattern)?;
+                let value = value.unwrap_or_default();
+                let starts = boundaries(&value, !longest);
+                match starts
+                    .into_iter()
+                    .find(|start| pattern.matches(&value[*start..]))
+                {
+                    Some(start) => value[..start].to_owned(),
+                    None => value,
+                }
+            }
+            ParamOp::Replace {
+                mode,
+                pattern,
+                replacement,
+            } => {
+                let empty = pattern.parts.is_empty();
+                let pattern = self.expand_pattern(pattern)?;
+                let replacement = self.expand_word(replacement)?;
+                let value = value.unwrap_or_default();
+                if empty && matches!(mode, ReplaceMode::First | ReplaceMode::All) {
+                    value
+                } else {
+                    replace(&value, &pattern, *mode, &replacement)
+                }
+            }
+        };
+        if quoted {
+            fields.push_str(&text);
+        } else {
+            fields.push_split(&text);
+        }
+        Ok(())
+    }
+
+    /// Expands the pattern of a `${...}` operator or `case` item. Quoted parts match
+    /// literally.
+    pub fn expand_pattern(&mut self, word: &Word) -> Result<Pattern> {
+        let mut text = String::new();
+        for part in &word.parts {
+            let expanded = self.expand_word(&Word {
+                parts: vec![part.clone()],
+            })?;
+            match part {
+                WordPart::Literal(_) | WordPart::Param(_) => text.push_str(&expanded),
+                _ => text.push_str(&pattern::escape(&expanded)),
+            }
+        }
+        Ok(Pattern::new(&text, self.options.extglob))
+    }
+
+    /// The value of a parameter, or `None` if it is unset. `name[n]` is an element of an
+    /// array, counting back from the end when negative, and `name[@]` all of them.
+    fn param(&self, name: &str) -> Option<String> {
+        if let Some((array, subscript)) = name.strip_suffix(']').and_then(|n| n.split_once('[')) {
+            let elements = self.elements(array)?;
+            return match subscript {
+                "@" | "*" => Some(elements.join(" ")),
+                _ => {
+                    let index = subscript.parse::<isize>().ok()?;
+                    let index = if index < 0 {
+                        elements.len().checked_sub(index.unsigned_abs())?
+                    } else {
+                        index.unsigned_abs()
+                    };
+                    elements.into_iter().nth(index)
+                }
+            };
+        }
+        match name {
+            "?" => Some(self.status.to_string()),
+            "!" => self.last_background.map(|pid| pid.to_string()),
+            "$" => Some(self.pid.to_string()),
+            "0" => Some(self.arg0.clone()),
+            "#" => Some(self.positional.len().to_string()),
+            "@" => Some(self.positional.join(" ")),
+            "*" => {
+                let separator = match self.vars.get("IFS") {
+                    Some(ifs) => ifs.chars().next().map(String::from).unwrap_or_default(),
+                    None => " ".to_owned(),
+                };
+                Some(self.positional.join(&separator))
+            }
+            _ if name.bytes().all(|b| b.is_ascii_digit()) => {
+                let index = name.parse::<usize>().ok()?;
+                self.positional.get(index.checked_sub(1)?).cloned()
+            }
+            "PIPESTATUS" => self.elements(name)?.into_iter().next(),
+            _ => self.vars.get(name).map(str::to_owned),
+        }
+    }
+
+    /// The elements of the array `name`. `PIPESTATUS` is the only array; any other set
+    /// variable is an array of its value alone.
+    fn elements(&self, name: &str) -> Option<Vec<String>> {
+        match name {
+            "PIPESTATUS" => Some(self.pipe_status.iter().map(i32::to_string).collect()),
+            _ => Some(vec![self.vars.get(name)?.to_owned()]),
+        }
+    }
+}
+
+fn home_dir(passwd: *const libc::passwd) -> Option<String> {
+    if passwd.is_null() {
+        return None;
+    }
+    let dir = unsafe { CStr::from_ptr((*passwd).pw_dir) };
+    Some(dir.to_string_lossy().into_owned())
+}
+
+/// The char boundaries of `s` from first to last, or last to first when `reverse` is set.
+fn boundaries(s: &str, reverse: bool) -> Vec<usize> {
+    let mut bounds: Vec<usize> = s.char_indices().map(|(i, _)| i).chain([s.len()]).collect();
+    if reverse {
+        bounds.reverse();
+    }
+    bounds
+}
+
+/// Replaces the longest matches of `pattern` in `value` as `${value/pattern/replacement}`
+/// does.
+fn replace(value: &str, pattern: &Pattern, mode: ReplaceMode, replacement: &str) -> String {
+    match mode {
+        ReplaceMode::Prefix => match boundaries(value, true)
+            .into_iter()
+            .find(|end| pattern.matches(&value[..*end]))
+        {
+            Some(end) => format!("{replacement}{}", &value[end..]),
+            None => value.to_owned(),
+        },
+        ReplaceMode::Suffix => match boundaries(value, false)
+            .into_iter()
+            .find(|start| pattern.matches(&value[*start..]))
+        {
+            Some(start) => format!("{}{replacement}", &value[..start]),
+            None => value.to_owned(),
+        },
+        ReplaceMode::First | ReplaceMode::All => {
+            let mut out = String::new();
+            let mut start = 0;
+            while start < value.len() {
+                let end = boundaries(&value[start..], true)
+                    .into_iter()
+                    .find(|end| *end > 0 && pattern.matches(&value[start..start + end]));
+                match end {
+                    Some(end) => {
+                        out.push_str(replacement);
+                        start += end;
+                        if mode == ReplaceMode::First {
+                            break;
+                        }
+                    }
+                    None => {
+                        let c = value[start..].chars().next().unwrap();
+                        out.push(c);
+                        start += c.len_utf8();
+                    }
+                }
+            }
+            out.push_str(&value[start..]);
+            out
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{ast::Command, parse};
+
+    fn shell() -> Shell {
+        let mut shell = Shell::new().unwrap();
+        shell.vars.set("path", "/usr/lib/libfoo.so.1");
+        shell.vars.set("pair", "a  b");
+        shell.vars.set("empty", "");
+        shell.vars.unset("unset");
+        shell
+    }
+
+    /// The fields the words of the command `src` expand to.
+    fn expand(shell: &mut Shell, src: &str) -> Result<Vec<String>> {
+        let list = parse(src).unwrap();
+        let Command::Simple(command) = &list.items[0].first.commands[0] else {
+            panic!("not a simple command: {src}");
+        };
+        let mut fields = Vec::new();
+        for word in &command.words {
+            fields.extend(shell.expand_fields(word)?);
+        }
+        Ok(fields)
+    }
+
+    fn expand_one(src: &str) -> String {
+        expand(&mut shell(), src).unwrap().join(",")
+    }
+
+    #[test]
+    fn tests_set_and_null_values() {
+        assert_eq!(expand_one("${empty-d} ${empty:-d} ${unset-d}"), "d,d");
+        assert_eq!(
+            expand_one("${path+alt} ${empty+alt} ${empty:+alt} ${unset+alt}"),
+            "alt,alt"
+        );
+        assert_eq!(expand_one("\"${empty:-}\""), "");
+        assert_eq!(expand(&mut shell(), "\"${unset}\"").unwrap(), [""]);
+    }
+
+    #[test]
+    fn assigns_defaults() {
+        let mut shell = shell();
+        assert_eq!(
+            expand(&mut shell, "${unset=one} $unset").unwrap(),
+            ["one", "one"]
+        );
+        assert_eq!(expand(&mut shell, "${empty:=two}").unwrap(), ["two"]);
+        assert_eq!(shell.vars.get("empty"), Some("two"));
+        assert!(expand(&mut shell, "${1=x}").is_err());
+    }
+
+    #[test]
+    fn reports_unset_parameters() {
+        let err = expand(&mut shell(), "${unset?not here}").unwrap_err();
+        assert_eq!(err.to_string(), "unset: not here");
+        let err = expand(&mut shell(), "${empty:?}").unwrap_err();
+        assert_eq!(err.to_string(), "empty: parameter null or not set");
+        assert_eq!(expand_one("${empty?}"), "");
+    }
+
+    #[test]
+    fn measures_length_in_characters() {
+        let mut shell = shell();
+        shell.vars.set("word", "héllo");
+        assert_eq!(
+            expand(&mut shell, "${#word} ${#unset}").unwrap(),
+            ["5", "0"]
+        );
+    }
+
+    #[test]
+    fn removes_shortest_and_longest_matches() {
+        assert_eq!(expand_one("${path#*/}"), "usr/lib/libfoo.so.1");
+        assert_eq!(expand_one("${path##*/}"), "libfoo.so.1");
+        assert_eq!(expand_one("${path%.*}"), "/usr/lib/libfoo.so");
+        assert_eq!(expand_one("${path%%.*}"), "/usr/lib/libfoo");
+        assert_eq!(expand_one("${path#nothing}"), "/usr/lib/libfoo.so.1");
+    }
+
+    #[test]
+    fn quoted_pattern_characters_match_literally() {
+        let mut shell = shell();
+        shell.vars.set("glob", "*.rs");
+        assert_eq!(expand(&mut shell, "${glob#'*'}").unwrap(), [".rs"]);
+        assert_eq!(
+            expand(&mut shell, "${path#\"*\"}").unwrap(),
+            ["/usr/lib/libfoo.so.1"]
+        );
+        shell.vars.set("star", "*");
+        assert_eq!(expand(&mut shell, "\"${glob##$star}\"").unwrap(), [""]);
+    }
+
+    #[test]
+    fn replaces_matches() {
+        assert_eq!(expand_one("${path/lib/LIB}"), "/usr/LIB/libfoo.so.1");
+        assert_eq!(expand_one("${path//lib/LIB}"), "/usr/LIB/LIBfoo.so.1");
+        assert_eq!(expand_one("${path/#\\/usr/~}"), "~/lib/libfoo.so.1");
+        assert_eq!(expand_one("${path/%.[0-9]}"), "/usr/lib/libfoo.so");
+        assert_eq!(expand_one("${path/#lib/x}"), "/usr/lib/libfoo.so.1");
+        assert_eq!(expand_one("${path//}"), "/usr/lib/libfoo.so.1");
+    }
+
+    #[test]
+    fn splits_unquoted_expansions_only() {
+        assert_eq!(expand_one("$pair"), "a,b");
+        assert_eq!(expand_one("\"$pair\""), "a  b");
+        assert_eq!(expand_one("${unset:-x  y} \"${unset:-x  y}\""), "x,y,x  y");
+        assert_eq!(expand_one("x${empty}y $empty"), "xy");
+    }
+
+    #[test]
+    fn expands_positional_parameters() {
+        let mut shell = shell();
+        shell.positional = vec!["a b".to_owned(), "c".to_owned()];
+        assert_eq!(expand(&mut shell, "\"$@\"").unwrap(), ["a b", "c"]);
+        assert_eq!(expand(&mut shell, "\"$*\" $#").unwrap(), ["a b c", "2"]);
+        assert_eq!(
+            expand(&mut shell, "$1 ${2} ${3-none}").unwrap(),
+            ["a", "b", "c", "none"]
+        );
+        shell.positional.clear();
+        assert!(expand(&mut shell, "\"$@\"").unwrap().is_empty());
+    }
+
+    #[test]
+    fn expands_tildes_at_the_start_only() {
+        let mut shell = shell();
+        shell.vars.set("HOME", "/home/potato");
+        shell.vars.set("PWD", "/work");
+        shell.vars.set("OLDPWD", "/old");
+        assert_eq!(
+            expand(&mut shell, "~ ~/src ~+ ~- a~ '~' ~nosuchuser/x").unwrap(),
+            [
+                "/home/potato",
+                "/home/potato/src",
+                "/work",
+                "/old",
+                "a~",
+                "~",
+                "~nosuchuser/x"
+            ]
+        );
+        assert_eq!(
+            expand(&mut shell, "~root").unwrap(),
+            [home_dir(unsafe { libc::getpwnam(c"root".as_ptr()) }).unwrap()]
+        );
+        let list = parse("PATH=~/bin:~/.local/bin:a~").unwrap();
+        let Command::Simple(command) = &list.items[0].first.commands[0] else {
+            panic!("not a simple command");
+        };
+        assert_eq!(
+            shell.expand_value(&command.assignments[0].value).unwrap(),
+            "/home/potato/bin:/home/potato/.local/bin:a~"
+        );
+    }
+
+    #[test]
+    fn indexes_pipestatus() {
+        let mut shell = shell();
+        shell.pipe_status = vec![0, 1, 141];
+        assert_eq!(
+            expand(
+                &mut shell,
+                "${PIPESTATUS[1]} ${PIPESTATUS[-1]} ${#PIPESTATUS[@]}"
+            )
+            .unwrap(),
+            ["1", "141", "3"]
+        );
+        assert_eq!(
+            expand(&mut shell, "${PIPESTATUS[3]-none} $PIPESTATUS").unwrap(),
+            ["none", "0"]
+        );
+        assert_eq!(
+            expand(&mut shell, "\"${PIPESTATUS[@]}\" \"${PIPESTATUS[*]}\"").unwrap(),
+            ["0", "1", "141", "0 1 141"]
+        );
+        assert_eq!(
+            expand(&mut shell, "${PIPESTATUS[*]}").unwrap(),
+            ["0", "1", "141"]
+        );
+        assert_eq!(
+            expand(&mut shell, "${path[0]} ${path[1]-none}").unwrap(),
+            ["/usr/lib/libfoo.so.1", "none"]
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use anyhow::{anyhow, bail, Result};
+use std::mem;
+
+use crate::{
+    parser::ast::{self, is_name, Function},
+    shell::Shell,
+};
+
+/// How deeply function calls can nest before the shell gives up, instead of overflowing
+/// its stack.
+const MAX_NESTING: usize = 1000;
+
+impl Shell {
+    pub fn define_function(&mut self, function: &Function) {
+        self.functions
+            .insert(function.name.clone(), function.clone());
+    }
+
+    /// Runs `function` with `args` as its positional parameters and returns its status.
+    /// Variables made `local` in it are restored when it returns.
+    pub fn call_function(&mut self, function: &Function, args: &[String]) -> Result<i32> {
+        if self.frames.len() >= MAX_NESTING {
+            bail!(
+                "{}: maximum function nesting level exceeded ({MAX_NESTING})",
+                function.name
+            );
+        }
+        let positional = mem::replace(&mut self.positional, args.to_vec());
+        let loop_depth = mem::take(&mut self.loop_depth);
+        self.frames.push(Vec::new());
+
+        let status = match &*function.body {
+            ast::Command::Compound(command, redirects) => {
+                self.open_redirects(redirects).and_then(|redirects| {
+                    let _saved = redirects.apply_saved()?;
+                    self.run_compound(command)
+                })
+            }
+            _ => unreachable!("function bodies are compound commands"),
+        };
+
+        for (name, var) in self.frames.pop().unwrap().into_iter().rev() {
+            self.vars.restore(&name, var);
+        }
+        self.loop_depth = loop_depth;
+        self.positional = positional;
+        self.returning = false;
+        status
+    }
+
+    /// Runs `local NAME[=value]...`, which gives the running function its own copy of
+    /// each variable, also seen by the functions it calls.
+    pub fn builtin_local(&mut self, args: &[String]) -> Result<i32> {
+        if self.frames.is_empty() {
+            eprintln!("local: can only be used in a function");
+            return Ok(1);
+        }
+        let mut status = 0;
+        for arg in args {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            if !is_name(name) {
+                eprintln!("local: `{arg}`: not a valid identifier");
+                status = 1;
+                continue;
+            }
+            let previous = self.vars.var(name).cloned();
+            let frame = self.frames.last_mut().unwrap();
+            let declared = frame.iter().any(|(local, _)| local == name);
+            if !declared {
+                frame.push((name.to_owned(), previous));
+            }
+            match value {
+                Some(value) => self.vars.set(name, value),
+                None if !declared => {
+                    self.vars.unset(name);
+                }
+                None => {}
+            }
+        }
+        Ok(status)
+    }
+
+    /// Runs `return [N]`, which ends the running function or sourced file with status `N`.
+    pub fn builtin_return(&mut self, args: &[String]) -> Result<i32> {
+        if self.frames.is_empty() && self.sourcing == 0 {
+            eprintln!("return: can only `return' from a function or sourced script");
+            return Ok(1);
+        }
+        let status = match args.first() {
+            Some(arg) => arg
+                .parse::<i32>()
+                .map_err(|_| anyhow!("return: {arg}: numeric argument required"))?,
+            None => self.status,
+        };
+        self.returning = true;
+        Ok(status & 0xff)
+    }
+}
@@ -0,0 +1,96 @@
+use std::fs;
+
+use crate::{
+    options::Options,
+    pattern::{self, Pattern},
+};
+
+/// The paths matching `pattern`, sorted. Names starting with `.` are only matched by a
+/// component that starts with `.` itself, unless `dotglob` is set.
+pub fn glob(pattern: &str, options: &Options) -> Vec<String> {
+    let (mut paths, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec!["/".to_owned()], rest),
+        None => (vec![String::new()], pattern),
+    };
+    let components: Vec<&str> = rest.split('/').collect();
+    for (i, component) in components.iter().enumerate() {
+        let last = i + 1 == components.len();
+        paths = if *component == "**" && options.globstar {
+            paths
+                .iter()
+                .flat_map(|path| recurse(path, last, options))
+                .collect()
+        } else if !pattern::has_wildcards(component, options.extglob) {
+            let name = pattern::unescape(component);
+            paths.iter().map(|path| join(path, &name)).collect()
+        } else {
+            let pattern = Pattern::new(component, options.extglob);
+            let hidden = component.starts_with('.') || component.starts_with("\\.");
+            paths
+                .iter()
+                .flat_map(|path| {
+                    read_names(path)
+                        .into_iter()
+                        .filter(|name| !name.starts_with('.') || hidden || options.dotglob)
+                        .filter(|name| pattern.matches(name))
+                        .map(|name| join(path, &name))
+                        .filter(|path| last || fs::metadata(path).is_ok_and(|meta| meta.is_dir()))
+                        .collect::<Vec<_>>()
+                })
+                .collect()
+        };
+    }
+    let mut paths: Vec<String> = paths
+        .into_iter()
+        .filter(|path| fs::symlink_metadata(path).is_ok())
+        .collect();
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+/// What `**` matches under `path`: the directory itself and every directory below it, or
+/// every file and directory below it when `**` is the last component.
+fn recurse(path: &str, last: bool, options: &Options) -> Vec<String> {
+    let mut found = Vec::new();
+    if !last {
+        found.push(path.to_owned());
+    }
+    let mut pending = vec![path.to_owned()];
+    while let Some(dir) = pending.pop() {
+        for name in read_names(&dir) {
+            if name.starts_with('.') && !options.dotglob {
+                continue;
+            }
+            let path = join(&dir, &name);
+            let is_dir = fs::symlink_metadata(&path).is_ok_and(|meta| meta.is_dir());
+            if is_dir {
+                pending.push(path.clone());
+            }
+            if is_dir || last {
+                found.push(path);
+            }
+        }
+    }
+    found
+}
+
+fn read_names(dir: &str) -> Vec<String> {
+    let dir = if dir.is_empty() { "." } else { dir };
+    fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn join(dir: &str, name: &str) -> String {
+    match dir {
+        "" => name.to_owned(),
+        "/" => format!("/{name}"),
+        dir => format!("{dir}/{name}"),
+    }
+}
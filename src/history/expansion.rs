@@ -0,0 +1,511 @@
+use anyhow::{anyhow, bail, Result};
+
+use super::History;
+use crate::{parser, shell::Shell};
+
+/// A line after history expansion.
+pub struct Expanded {
+    pub line: String,
+    /// A `:p` modifier asked for the line to be shown and saved but not run.
+    pub print_only: bool,
+}
+
+/// The state of expanding one line.
+struct Expansion<'a> {
+    history: &'a [History],
+    /// The expanded line so far, also the event `!#`.
+    line: String,
+    print_only: bool,
+    /// The last `:s` substitution, repeated by `:&`.
+    substitution: Option<(String, String)>,
+    /// The word found by the last `!?text?` search, designated by `%`.
+    found: Option<String>,
+}
+
+impl Shell {
+    /// Expands the history references in `line`, as in csh: an event (`!!`, `!n`, `!-n`,
+    /// `!prefix`, `!?text?` or `^old^new^`), optionally followed by a word designator and
+    /// modifiers. `!` is left alone in single quotes, after a backslash, and before a
+    /// blank, `=` or `(`. Returns `None` when there is nothing to expand.
+    pub fn expand_history(&self, line: &str) -> Result<Option<Expanded>> {
+        if !line.contains(['!', '^']) {
+            return Ok(None);
+        }
+        let mut expansion = Expansion {
+            history: &self.history,
+            line: String::new(),
+            print_only: false,
+            substitution: None,
+            found: None,
+        };
+        let mut changed = false;
+        let mut rest = line;
+        if let Some(quick) = line.strip_prefix('^') {
+            rest = expansion.quick_substitution(quick)?;
+            changed = true;
+        }
+        let mut quote = None;
+        while let Some(c) = rest.chars().next() {
+            match (c, quote) {
+                ('\\', None | Some('"')) => {
+                    let len = rest[1..].chars().next().map_or(1, |c| 1 + c.len_utf8());
+                    expansion.line.push_str(&rest[..len]);
+                    rest = &rest[len..];
+                    continue;
+                }
+                ('\'' | '"', None) => quote = Some(c),
+                ('\'' | '"', Some(open)) if c == open => quote = None,
+                ('!', None | Some('"')) if expansion.expands(&rest[1..], quote.is_some()) => {
+                    let reference = &rest[1..];
+                    let token = &reference[..reference
+                        .find(char::is_whitespace)
+                        .unwrap_or(reference.len())];
+                    rest = expansion
+                        .reference(reference, quote.is_some())
+                        .map_err(|err| anyhow!("!{token}: {err}"))?;
+                    changed = true;
+                    continue;
+                }
+                _ => {}
+            }
+            expansion.line.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+        Ok(changed.then_some(Expanded {
+            line: expansion.line,
+            print_only: expansion.print_only,
+        }))
+    }
+}
+
+impl Expansion<'_> {
+    /// Whether a `!` followed by `rest` starts a history reference. `$!` and `[!...]` are
+    /// left to the shell.
+    fn expands(&self, rest: &str, in_double_quotes: bool) -> bool {
+        let next = rest.chars().next();
+        let previous = self.line.chars().next_back();
+        let quote = if in_double_quotes { Some('"') } else { None };
+        !(matches!(next, None | Some(' ' | '\t' | '\n' | '\r' | '=' | '('))
+            || next == quote
+            || matches!(previous, Some('$' | '[')))
+    }
+
+    /// Expands `^old^new^`, the previous command with `old` replaced by `new`, and returns
+    /// the rest of the line.
+    fn quick_substitution<'s>(&mut self, quick: &'s str) -> Result<&'s str> {
+        let (old, rest) = delimited(quick, '^');
+        let (new, rest) = delimited(rest, '^');
+        let name = format!("^{old}^{new}");
+        let command = self
+            .previous()
+            .map_err(|err| anyhow!("{name}: {err}"))?
+            .to_owned();
+        let text = self
+            .substitute(command, old, &new, false)
+            .map_err(|err| anyhow!("{name}: {err}"))?;
+        self.line.push_str(&text);
+        Ok(rest)
+    }
+
+    /// Expands the history reference at the start of `reference`, just after its `!`, and
+    /// returns the rest of the line.
+    fn reference<'s>(&mut self, reference: &'s str, in_double_quotes: bool) -> Result<&'s str> {
+        let (command, rest) = self.event(reference, in_double_quotes)?;
+        let (mut text, mut rest) = self.designated_words(&command, rest)?;
+        while let Some(modifier) = rest.strip_prefix(':') {
+            let Some(after) = self.modify(&mut text, modifier)? else {
+                break;
+            };
+            rest = after;
+        }
+        self.line.push_str(&text);
+        Ok(rest)
+    }
+
+    /// Finds the command an event designator refers to.
+    fn event<'s>(
+        &mut self,
+        reference: &'s str,
+        in_double_quotes: bool,
+    ) -> Result<(String, &'s str)> {
+        let numeric = reference
+            .strip_prefix('-')
+            .unwrap_or(reference)
+            .starts_with(|c: char| c.is_ascii_digit());
+        match reference.chars().next() {
+            Some('!') => Ok((self.previous()?.to_owned(), &reference[1..])),
+            Some('#') => Ok((self.line.clone(), &reference[1..])),
+            Some(':' | '^' | '$' | '*' | '%') => Ok((self.previous()?.to_owned(), reference)),
+            Some('?') => {
+                let body = &reference[1..];
+                let (text, rest) = match body.find('?') {
+                    Some(end) => (&body[..end], &body[end + 1..]),
+                    None => (body, ""),
+                };
+                let command = self.search(|command| command.contains(text))?.to_owned();
+                self.found = parser::split_words(&command)
+                    .into_iter()
+                    .find(|word| word.contains(text))
+                    .map(str::to_owned);
+                Ok((command, rest))
+            }
+            _ if numeric => {
+                let end = 1 + reference[1..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(reference.len() - 1);
+                let number: isize = reference[..end]
+                    .parse()
+                    .map_err(|_| anyhow!("event not found"))?;
+                let index = if number < 0 {
+                    self.history.len() as isize + number
+                } else {
+                    number - 1
+                };
+                let entry = usize::try_from(index)
+                    .ok()
+                    .and_then(|index| self.history.get(index))
+                    .ok_or_else(|| anyhow!("event not found"))?;
+                Ok((entry.command.clone(), &reference[end..]))
+            }
+            _ => {
+                let end = reference
+                    .find(|c: char| {
+                        c.is_whitespace()
+                            || ":;&|<>()'".contains(c)
+                            || (in_double_quotes && c == '"')
+                    })
+                    .unwrap_or(reference.len());
+                let prefix = &reference[..end];
+                let command = self.search(|command| command.starts_with(prefix))?;
+                Ok((command.to_owned(), &reference[end..]))
+            }
+        }
+    }
+
+    fn previous(&self) -> Result<&str> {
+        self.search(|_| true)
+    }
+
+    /// The most recent command that matches.
+    fn search(&self, matches: impl Fn(&str) -> bool) -> Result<&str> {
+        self.history
+            .iter()
+            .rev()
+            .map(|entry| entry.command.as_str())
+            .find(|command| matches(command))
+            .ok_or_else(|| anyhow!("event not found"))
+    }
+
+    /// Selects the words of `command` named by a word designator at the start of `rest`:
+    /// `:n`, `:x-y`, `:x-`, `:x*`, `^`, `$`, `*` or `%`, the colon being optional before
+    /// the last four. Without one the whole command is used.
+    fn designated_words<'s>(&self, command: &str, rest: &'s str) -> Result<(String, &'s str)> {
+        let spec = match rest.strip_prefix(':') {
+            Some(spec) if spec.starts_with(|c: char| c.is_ascii_digit() || "^$*-%".contains(c)) => {
+                spec
+            }
+            _ if rest.starts_with(['^', '$', '*', '%']) => rest,
+            _ => return Ok((command.to_owned(), rest)),
+        };
+        let words = parser::split_words(command);
+        let last = words.len().saturating_sub(1);
+        let (start, rest) = match spec.chars().next() {
+            Some('*') => return Ok((words.get(1..).unwrap_or_default().join(" "), &spec[1..])),
+            Some('^') => (1, &spec[1..]),
+            Some('$') => (last, &spec[1..]),
+            Some('%') => {
+                let found = self.found.as_deref();
+                let index = words
+                    .iter()
+                    .position(|word| Some(*word) == found)
+                    .ok_or_else(|| anyhow!("bad word specifier"))?;
+                (index, &spec[1..])
+            }
+            Some('-') => (0, spec),
+            _ => number(spec),
+        };
+        let (end, rest) = if let Some(rest) = rest.strip_prefix('*') {
+            if start > last {
+                return Ok((String::new(), rest));
+            }
+            (last, rest)
+        } else if let Some(range) = rest.strip_prefix('-') {
+            match range.chars().next() {
+                Some('$') => (last, &range[1..]),
+                Some(c) if c.is_ascii_digit() => number(range),
+                _ => (
+                    last.checked_sub(1)
+                        .ok_or_else(|| anyhow!("bad word specifier"))?,
+                    range,
+                ),
+            }
+        } else {
+            (start, rest)
+        };
+        if words.is_empty() || start > end || end > last {
+            bail!("bad word specifier");
+        }
+        Ok((words[start..=end].join(" "), rest))
+    }
+
+    /// Applies the modifier at the start of `modifier`, just after its colon, to `text` and
+    /// returns the rest of the line, or `None` if it is not a modifier.
+    fn modify<'s>(&mut self, text: &mut String, modifier: &'s str) -> Result<Option<&'s str>> {
+        let rest = &modifier[modifier.chars().next().map_or(0, char::len_utf8)..];
+        match modifier.chars().next() {
+            Some('h') => {
+                if let Some(slash) = text.rfind('/') {
+                    text.truncate(slash);
+                }
+            }
+            Some('t') => {
+                if let Some(slash) = text.rfind('/') {
+                    text.replace_range(..=slash, "");
+                }
+            }
+            Some('r') => {
+                if let Some(dot) = extension(text) {
+                    text.truncate(dot);
+                }
+            }
+            Some('e') => match extension(text) {
+                Some(dot) => {
+                    text.replace_range(..dot, "");
+                }
+                None => text.clear(),
+            },
+            Some('p') => self.print_only = true,
+            Some('q') => *text = format!("'{}'", text.replace('\'', r"'\''")),
+            Some('s') => return self.substitute_modifier(text, rest, false).map(Some),
+            Some('&') => return self.repeat_substitution(text, false).map(|_| Some(rest)),
+            Some('g' | 'a') => match rest.chars().next() {
+                Some('s') => return self.substitute_modifier(text, &rest[1..], true).map(Some),
+                Some('&') => {
+                    return self
+                        .repeat_substitution(text, true)
+                        .map(|_| Some(&rest[1..]))
+                }
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        }
+        Ok(Some(rest))
+    }
+
+    /// Applies `s/old/new/`, given as `spec` just after the `s`, to `text`. Any character
+    /// can stand for the slashes, an empty `old` means the last one used, and `&` in `new`
+    /// stands for `old`.
+    fn substitute_modifier<'s>(
+        &mut self,
+        text: &mut String,
+        spec: &'s str,
+        global: bool,
+    ) -> Result<&'s str> {
+        let Some(delimiter) = spec.chars().next() else {
+            bail!("bad substitution");
+        };
+        let (old, rest) = delimited(&spec[delimiter.len_utf8()..], delimiter);
+        let (new, rest) = delimited(rest, delimiter);
+        *text = self.substitute(std::mem::take(text), old, &new, global)?;
+        Ok(rest)
+    }
+
+    /// Applies the last substitution again for `:&` and `:g&`.
+    fn repeat_substitution(&mut self, text: &mut String, global: bool) -> Result<()> {
+        let Some((old, new)) = self.substitution.clone() else {
+            bail!("no previous substitution");
+        };
+        *text = self.substitute(std::mem::take(text), old, &new, global)?;
+        Ok(())
+    }
+
+    fn substitute(&mut self, text: String, old: String, new: &str, global: bool) -> Result<String> {
+        let old = if !old.is_empty() {
+            old
+        } else if let Some((old, _)) = &self.substitution {
+            old.clone()
+        } else {
+            bail!("no previous substitution");
+        };
+        if !text.contains(&old) {
+            bail!("substitution failed");
+        }
+        let mut replacement = String::new();
+        let mut chars = new.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if chars.clone().next() == Some('&') => {
+                    replacement.push('&');
+                    chars.next();
+                }
+                '&' => replacement.push_str(&old),
+                _ => replacement.push(c),
+            }
+        }
+        let text = if global {
+            text.replace(&old, &replacement)
+        } else {
+            text.replacen(&old, &replacement, 1)
+        };
+        self.substitution = Some((old, new.to_owned()));
+        Ok(text)
+    }
+}
+
+/// Reads `text` up to an unescaped `delimiter`, which may be left out at the end.
+/// Returns the text with escaped delimiters unescaped and what follows the delimiter.
+fn delimited(text: &str, delimiter: char) -> (String, &str) {
+    let mut out = String::new();
+    let mut chars = text.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' if chars.clone().next().map(|(_, c)| c) == Some(delimiter) => {
+                out.push(delimiter);
+                chars.next();
+            }
+            _ if c == delimiter => return (out, &text[index + c.len_utf8()..]),
+            _ => out.push(c),
+        }
+    }
+    (out, "")
+}
+
+/// Reads the number at the start of `text`.
+fn number(text: &str) -> (usize, &str) {
+    let end = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    (text[..end].parse().unwrap_or(usize::MAX), &text[end..])
+}
+
+/// Where the `.suffix` of the last path component of `text` starts.
+fn extension(text: &str) -> Option<usize> {
+    let dot = text.rfind('.')?;
+    (!text[dot..].contains('/')).then_some(dot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Local;
+
+    fn shell() -> Shell {
+        let mut shell = Shell::new().unwrap();
+        shell.history = [
+            "cd /usr/src/linux",
+            "tar xzf archive.tar.gz -C /tmp",
+            "grep -rn needle src/main.rs",
+            "echo one two three",
+        ]
+        .into_iter()
+        .map(|command| History::bare(command.to_owned(), Local::now()))
+        .collect();
+        shell
+    }
+
+    fn expand(line: &str) -> String {
+        match shell().expand_history(line) {
+            Ok(Some(expanded)) => expanded.line,
+            Ok(None) => line.to_owned(),
+            Err(err) => format!("error: {err}"),
+        }
+    }
+
+    #[test]
+    fn finds_events() {
+        assert_eq!(expand("!!"), "echo one two three");
+        assert_eq!(expand("sudo !!"), "sudo echo one two three");
+        assert_eq!(expand("!1"), "cd /usr/src/linux");
+        assert_eq!(expand("!-2"), "grep -rn needle src/main.rs");
+        assert_eq!(expand("!ta"), "tar xzf archive.tar.gz -C /tmp");
+        assert_eq!(expand("!?needle?; ls"), "grep -rn needle src/main.rs; ls");
+        assert_eq!(expand("!?linux"), "cd /usr/src/linux");
+        assert_eq!(expand("echo !#"), "echo echo ");
+    }
+
+    #[test]
+    fn reports_missing_events() {
+        assert_eq!(expand("!9"), "error: !9: event not found");
+        assert_eq!(expand("!-9"), "error: !-9: event not found");
+        assert_eq!(expand("!nothing"), "error: !nothing: event not found");
+        let empty = Shell::new().unwrap();
+        assert!(empty.expand_history("!!").is_err());
+    }
+
+    #[test]
+    fn leaves_other_exclamation_marks_alone() {
+        for line in [
+            "echo hi!",
+            "echo ! x",
+            "x!=y",
+            "echo '!!'",
+            "echo \\!!",
+            "echo $!",
+            "ls [!a]*",
+            "echo !(x)",
+            "echo \"hi!\"",
+        ] {
+            assert!(shell().expand_history(line).unwrap().is_none(), "{line}");
+        }
+        assert_eq!(expand("echo \"!!\""), "echo \"echo one two three\"");
+    }
+
+    #[test]
+    fn designates_words() {
+        assert_eq!(expand("!!:0"), "echo");
+        assert_eq!(expand("!!:2"), "two");
+        assert_eq!(expand("!!^ !!$"), "one three");
+        assert_eq!(expand("!!:1-2"), "one two");
+        assert_eq!(expand("!!:-2"), "echo one two");
+        assert_eq!(expand("!!:2-"), "two");
+        assert_eq!(expand("!!:2*"), "two three");
+        assert_eq!(expand("!!*"), "one two three");
+        assert_eq!(expand("!tar:$"), "/tmp");
+        assert_eq!(expand("!?need?%"), "needle");
+        assert_eq!(expand("!!:7"), "error: !!:7: bad word specifier");
+        assert_eq!(expand("!!:3-1"), "error: !!:3-1: bad word specifier");
+    }
+
+    #[test]
+    fn applies_modifiers() {
+        assert_eq!(expand("!cd:$:h"), "/usr/src");
+        assert_eq!(expand("!cd:$:t"), "linux");
+        assert_eq!(expand("!tar:2:r"), "archive.tar");
+        assert_eq!(expand("!tar:2:e"), ".gz");
+        assert_eq!(expand("!tar:2:r:r"), "archive");
+        assert_eq!(expand("!grep:$:h:t"), "src");
+        assert_eq!(expand("!!:2:q"), "'two'");
+        assert_eq!(expand("!!:s/one/1/"), "echo 1 two three");
+        assert_eq!(expand("!!:s|o|0"), "ech0 one two three");
+        assert_eq!(expand("!!:gs/o/0/"), "ech0 0ne tw0 three");
+        assert_eq!(expand("!!:s/two/[&]/"), "echo one [two] three");
+        assert_eq!(expand("!!:s/two/\\&/"), "echo one & three");
+        assert_eq!(expand("!!:s/e/E/:&"), "Echo onE two three");
+        assert_eq!(expand("!!:s/x/y/"), "error: !!:s/x/y/: substitution failed");
+        assert_eq!(expand("!!:&"), "error: !!:&: no previous substitution");
+    }
+
+    #[test]
+    fn print_modifier_only_shows_the_line() {
+        let expanded = shell().expand_history("!!:p").unwrap().unwrap();
+        assert!(expanded.print_only);
+        assert_eq!(expanded.line, "echo one two three");
+    }
+
+    #[test]
+    fn quick_substitution_replaces_in_the_previous_command() {
+        assert_eq!(expand("^three^3^"), "echo one two 3");
+        assert_eq!(expand("^two^2^ four"), "echo one 2 three four");
+        assert_eq!(expand("^one^"), "echo  two three");
+        assert_eq!(expand("^four^4"), "error: ^four^4: substitution failed");
+    }
+
+    #[test]
+    fn reads_delimited_text() {
+        assert_eq!(delimited("a\\/b/c", '/'), ("a/b".to_owned(), "c"));
+        assert_eq!(delimited("abc", '/'), ("abc".to_owned(), ""));
+        assert_eq!(number("12-x"), (12, "-x"));
+        assert_eq!(extension("dir.d/file"), None);
+        assert_eq!(extension("a/b.tar.gz"), Some(7));
+    }
+}
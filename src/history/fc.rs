@@ -0,0 +1,200 @@
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use colored::Colorize;
+use std::{
+    env,
+    ffi::{CString, OsString},
+    fs::{self, File},
+    io::{self, Write},
+    os::{fd::FromRawFd, unix::ffi::OsStringExt},
+    path::PathBuf,
+    time::Instant,
+};
+
+use crate::{builtins::StdoutColors, shell::Shell, vars::quote};
+
+impl Shell {
+    /// Runs `fc [-e EDITOR] [FIRST [LAST]]`, which opens the commands from FIRST to LAST in
+    /// `$FCEDIT`, `$EDITOR` or `vi` and runs what was saved, `fc -l [-nr] [FIRST [LAST]]`,
+    /// which lists them, and `fc -s [OLD=NEW] [FIRST]`, which runs one again with OLD
+    /// replaced. FIRST and LAST are entry numbers, negative to count back, or prefixes.
+    pub fn builtin_fc(&mut self, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        let (mut editor, mut list, mut numbered, mut reverse, mut again) =
+            (None, false, true, false, false);
+        let mut args = args.iter().peekable();
+        while let Some(arg) = args.next_if(|arg| {
+            arg.starts_with('-')
+                && arg.len() > 1
+                && !arg[1..].starts_with(|c: char| c.is_ascii_digit())
+        }) {
+            if arg == "--" {
+                break;
+            }
+            for flag in arg[1..].chars() {
+                match flag {
+                    'e' => match args.next() {
+                        Some(name) => editor = Some(name.clone()),
+                        None => {
+                            eprintln!("fc: -e: option requires an argument");
+                            return Ok(2);
+                        }
+                    },
+                    'l' => list = true,
+                    'n' => numbered = false,
+                    'r' => reverse = true,
+                    's' => again = true,
+                    _ => {
+                        eprintln!("fc: -{flag}: invalid option");
+                        return Ok(2);
+                    }
+                }
+            }
+        }
+        let args: Vec<&String> = args.collect();
+        if again || editor.as_deref() == Some("-") {
+            return self.fc_again(&args, out);
+        }
+
+        let default = if list { "-16" } else { "-1" };
+        let first = args.first().map_or(default, |arg| arg.as_str());
+        let last = match (args.get(1), list) {
+            (Some(last), _) => last.as_str(),
+            (None, true) => "-1",
+            (None, false) => first,
+        };
+        let (Some(first), Some(last)) = (self.fc_position(first), self.fc_position(last)) else {
+            eprintln!("fc: history specification out of range");
+            return Ok(1);
+        };
+        let mut indices: Vec<usize> = if first <= last {
+            (first..=last).collect()
+        } else {
+            (last..=first).rev().collect()
+        };
+        if reverse {
+            indices.reverse();
+        }
+
+        if list {
+            let _colors = StdoutColors::new();
+            for index in indices {
+                let command = self.history[index].command.purple();
+                if numbered {
+                    writeln!(out, "{:>5}  {}", index + 1, command)?;
+                } else {
+                    writeln!(out, "{command}")?;
+                }
+            }
+            return Ok(0);
+        }
+        let mut script = String::new();
+        for index in indices {
+            script += &self.history[index].command;
+            script.push('\n');
+        }
+        let (mut file, path) = temp_file("potato-fc-", ".sh")
+            .map_err(|err| anyhow!("fc: {}: {err}", env::temp_dir().display()))?;
+        file.write_all(script.as_bytes())
+            .map_err(|err| anyhow!("fc: {}: {err}", path.display()))?;
+        drop(file);
+        let editor = editor
+            .or_else(|| self.vars.get("FCEDIT").map(str::to_owned))
+            .or_else(|| self.vars.get("EDITOR").map(str::to_owned))
+            .unwrap_or_else(|| "vi".to_owned());
+        let edited = self
+            .handel_command(&format!("{editor} {}", quote(&path.to_string_lossy())))
+            .and_then(|_| Ok(fs::read_to_string(&path)?));
+        let _ = fs::remove_file(&path);
+        if self.status != 0 {
+            return Ok(self.status);
+        }
+        self.fc_run(edited?.trim_end(), out)
+    }
+
+    /// Runs `fc -s [OLD=NEW] [FIRST]`.
+    fn fc_again(&mut self, args: &[&String], out: &mut dyn Write) -> Result<i32> {
+        let (replace, args) = match args.split_first() {
+            Some((first, rest)) if first.contains('=') => (first.split_once('='), rest),
+            _ => (None, args),
+        };
+        let Some(index) = self.fc_position(args.first().map_or("-1", |arg| arg.as_str())) else {
+            eprintln!("fc: no command found");
+            return Ok(1);
+        };
+        let mut command = self.history[index].command.clone();
+        if let Some((old, new)) = replace.filter(|(old, _)| !old.is_empty()) {
+            command = command.replace(old, new);
+        }
+        self.fc_run(&command, out)
+    }
+
+    /// Shows `commands`, puts them in the history in place of the `fc` that ran them, and
+    /// runs them.
+    fn fc_run(&mut self, commands: &str, out: &mut dyn Write) -> Result<i32> {
+        if commands.trim().is_empty() {
+            return Ok(0);
+        }
+        writeln!(out, "{commands}")?;
+        self.rl.add_history_entry(commands)?;
+        let (date, started) = (Local::now(), Instant::now());
+        let cwd = env::current_dir().map_or_else(
+            |_| self.current_path.clone(),
+            |dir| dir.to_string_lossy().into_owned(),
+        );
+        let result = self.handel_command(commands);
+        self.record_history(commands, date, cwd, started.elapsed());
+        self.fc_ran = true;
+        result?;
+        Ok(self.status)
+    }
+
+    /// The index of the entry FIRST or LAST of `fc` names: a number, negative to count
+    /// back from the last entry, or the prefix of the most recent command starting with it.
+    /// Numbers past either end mean the first or last entry.
+    fn fc_position(&self, spec: &str) -> Option<usize> {
+        let last = self.history.len().checked_sub(1)?;
+        match spec.parse::<isize>() {
+            Ok(number) if number < 0 => Some(last.saturating_sub(number.unsigned_abs() - 1)),
+            Ok(number) => Some((number.unsigned_abs().max(1) - 1).min(last)),
+            Err(_) => self
+                .history
+                .iter()
+                .rposition(|entry| entry.command.starts_with(spec)),
+        }
+    }
+}
+
+/// Creates a new file in the temporary directory that only we can read, named `prefix`,
+/// random characters and `suffix`, and returns it with its path.
+fn temp_file(prefix: &str, suffix: &str) -> io::Result<(File, PathBuf)> {
+    let mut template = env::temp_dir().into_os_string().into_vec();
+    template.extend_from_slice(format!("/{prefix}XXXXXX{suffix}").as_bytes());
+    let template = CString::new(template)?.into_raw();
+    // mkstemps picks names until one does not exist yet, and creates it with mode 0600.
+    let fd = unsafe { libc::mkstemps(template, suffix.len() as i32) };
+    let path = unsafe { CString::from_raw(template) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let file = unsafe { File::from_raw_fd(fd) };
+    Ok((file, OsString::from_vec(path.into_bytes()).into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn temp_files_are_new_and_private() {
+        let (_, first) = temp_file("potato-test-", ".sh").unwrap();
+        let (_, second) = temp_file("potato-test-", ".sh").unwrap();
+        assert_ne!(first, second);
+        for path in [first, second] {
+            assert!(path.to_string_lossy().ends_with(".sh"));
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            fs::remove_file(&path).unwrap();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}
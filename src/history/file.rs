@@ -0,0 +1,255 @@
+use anyhow::{anyhow, Result};
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::fs::{MetadataExt, OpenOptionsExt},
+    },
+    path::{Path, PathBuf},
+};
+
+use super::{parse, History, LEGACY_PATH, VERSION};
+use crate::shell::Shell;
+
+/// An advisory lock on the history file, so that shells sharing it neither interleave nor
+/// lose each other's writes. It is released when dropped.
+struct Lock(RawFd);
+
+impl Lock {
+    fn new(file: &File, operation: libc::c_int) -> io::Result<Self> {
+        loop {
+            if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+                return Ok(Self(file.as_raw_fd()));
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        unsafe { libc::flock(self.0, libc::LOCK_UN) };
+    }
+}
+
+impl Shell {
+    /// Where history is kept: `$POTATO_HISTFILE`, or `potato-shell/history` in
+    /// `$XDG_STATE_HOME`, which defaults to `~/.local/state`.
+    pub fn history_path(&self) -> Option<PathBuf> {
+        let path = match self
+            .vars
+            .get("POTATO_HISTFILE")
+            .filter(|path| !path.is_empty())
+        {
+            Some(path) => PathBuf::from(path),
+            None => {
+                let state = match self.vars.get("XDG_STATE_HOME") {
+                    Some(dir) if Path::new(dir).is_absolute() => PathBuf::from(dir),
+                    _ => Path::new(self.vars.get("HOME")?).join(".local/state"),
+                };
+                state.join("potato-shell").join("history")
+            }
+        };
+        Some(env::current_dir().ok()?.join(path))
+    }
+
+    /// Loads the history file at `path` into the shell and the line editor. From then on
+    /// each command is appended to it. A file in an older format is rewritten in the
+    /// current one first, and a new file starts with the history kept at `LEGACY_PATH`.
+    pub fn load_history(&mut self, path: PathBuf) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| anyhow!("{}: {err}", dir.display()))?;
+        }
+        let created = !path.exists();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o600)
+            .open(&path)
+            .map_err(|err| anyhow!("{}: {err}", path.display()))?;
+        let _lock = Lock::new(&file, libc::LOCK_EX)?;
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+        let legacy = env::current_dir()?.join(LEGACY_PATH);
+        let mut entries = if created && text.is_empty() && legacy.is_file() {
+            text = fs::read_to_string(&legacy)
+                .map_err(|err| anyhow!("{}: {err}", legacy.display()))?;
+            parse(&legacy, &text, fs::metadata(&legacy)?.modified()?.into())?
+        } else {
+            parse(&path, &text, file.metadata()?.modified()?.into())?
+        };
+        entries.sort_by_key(|entry| entry.date);
+        if !text.is_empty() && text.lines().next() != Some(VERSION) {
+            replace_contents(&mut file, &entries)?;
+        }
+        let metadata = file.metadata()?;
+        self.history_read = metadata.len();
+        self.history_id = (metadata.dev(), metadata.ino());
+        self.history = entries;
+        self.history_file = Some(path);
+        self.sync_line_editor()
+    }
+
+    /// Appends `entries` to the history file, if there is one.
+    pub(super) fn append_history(&self, entries: &[History]) -> Result<()> {
+        let Some(path) = &self.history_file else {
+            return Ok(());
+        };
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(path)
+            .map_err(|err| anyhow!("{}: {err}", path.display()))?;
+        let _lock = Lock::new(&file, libc::LOCK_EX)?;
+        let mut text = String::new();
+        if file.metadata()?.len() == 0 {
+            text = format!("{VERSION}\n");
+        }
+        text += &serialize(entries)?;
+        file.write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    /// Rewrites the history file, in date order, with its entries as `edit` leaves them.
+    /// These include what other sessions added.
+    pub fn rewrite_history(&mut self, edit: impl FnOnce(&mut Vec<History>)) -> Result<()> {
+        let Some(path) = &self.history_file else {
+            return Ok(());
+        };
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|err| anyhow!("{}: {err}", path.display()))?;
+        let _lock = Lock::new(&file, libc::LOCK_EX)?;
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+        let mut entries = parse(path, &text, file.metadata()?.modified()?.into())?;
+        entries.sort_by_key(|entry| entry.date);
+        edit(&mut entries);
+        replace_contents(&mut file, &entries)?;
+        self.history_read = file.metadata()?.len();
+        Ok(())
+    }
+
+    /// Adds the entries other sessions appended to the history file since it was last
+    /// read, for `shopt -s sharehistory`. If another session rewrote the file, or it was
+    /// replaced or shrank, it is loaded again.
+    pub fn read_shared_history(&mut self) -> Result<()> {
+        let Some(path) = self.history_file.clone() else {
+            return Ok(());
+        };
+        let mut file = File::open(&path).map_err(|err| anyhow!("{}: {err}", path.display()))?;
+        let lock = Lock::new(&file, libc::LOCK_SH)?;
+        let metadata = file.metadata()?;
+        let len = metadata.len();
+        if (metadata.dev(), metadata.ino()) != self.history_id || len < self.history_read {
+            drop(lock);
+            return self.load_history(path);
+        }
+        if len == self.history_read {
+            return Ok(());
+        }
+        let mut text = String::new();
+        file.seek(SeekFrom::Start(self.history_read))?;
+        file.read_to_string(&mut text)?;
+        let entries: Option<Vec<History>> = text
+            .lines()
+            .filter(|line| !line.is_empty() && *line != VERSION)
+            .map(|line| serde_json::from_str(line).ok())
+            .collect();
+        let Some(entries) = entries else {
+            drop(lock);
+            return self.load_history(path);
+        };
+        for entry in entries {
+            if entry.session.as_ref() != Some(&self.session) {
+                self.rl.add_history_entry(entry.command.as_str())?;
+                self.history.push(entry);
+            }
+        }
+        self.history_read = len;
+        Ok(())
+    }
+}
+
+/// Replaces what `file` holds with `entries` in the current format.
+fn replace_contents(file: &mut File, entries: &[History]) -> Result<()> {
+    let text = format!("{VERSION}\n{}", serialize(entries)?);
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(text.as_bytes())?;
+    Ok(())
+}
+
+/// The lines of the history file holding `entries`.
+fn serialize(entries: &[History]) -> Result<String> {
+    let mut text = String::new();
+    for entry in entries {
+        text += &serde_json::to_string(entry)?;
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, TimeZone};
+    use std::{os::unix::fs::PermissionsExt, process};
+
+    fn write_history(path: &Path, commands: &[&str]) {
+        let entries: Vec<History> = commands
+            .iter()
+            .map(|command| {
+                let date = Local.timestamp_opt(1_700_000_000, 0).unwrap();
+                History::bare(command.to_string(), date)
+            })
+            .collect();
+        let text = format!("{VERSION}\n{}", serialize(&entries).unwrap());
+        fs::write(path, text).unwrap();
+    }
+
+    fn commands(shell: &Shell) -> Vec<&str> {
+        shell
+            .history
+            .iter()
+            .map(|entry| entry.command.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn shared_history_reloads_a_replaced_or_shorter_file() {
+        let dir = env::temp_dir().join(format!("potato-history-test-{}", process::id()));
+        let path = dir.join("history");
+        let mut shell = Shell::new().unwrap();
+        shell.load_history(path.clone()).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        write_history(&path, &["one", "two"]);
+        shell.read_shared_history().unwrap();
+        assert_eq!(commands(&shell), ["one", "two"]);
+
+        write_history(&path, &["three"]);
+        shell.read_shared_history().unwrap();
+        assert_eq!(commands(&shell), ["three"]);
+
+        // Past the length read so far, the new file looks like an appended entry.
+        let other = dir.join("other");
+        write_history(&other, &["THREE", "four"]);
+        fs::rename(&other, &path).unwrap();
+        shell.read_shared_history().unwrap();
+        assert_eq!(commands(&shell), ["THREE", "four"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
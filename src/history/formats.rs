@@ -0,0 +1,367 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Local, TimeZone};
+use colored::Colorize;
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    str::FromStr,
+};
+
+use super::{parse, unescape, History};
+use crate::shell::Shell;
+
+/// A format history is exported to or imported from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Json,
+    Csv,
+    /// The shell's own history file.
+    Potato,
+    Bash,
+    /// zsh, with or without `EXTENDED_HISTORY`.
+    Zsh,
+    Fish,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "potato" => Ok(Format::Potato),
+            "bash" => Ok(Format::Bash),
+            "zsh" => Ok(Format::Zsh),
+            "fish" => Ok(Format::Fish),
+            _ => bail!("{s}: unknown history format"),
+        }
+    }
+}
+
+impl Shell {
+    /// Runs `history export [--format json|csv] [file]`, which writes the history to `file`
+    /// or standard output. The format defaults to csv for a `.csv` file and json otherwise.
+    pub(super) fn history_export(&mut self, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        let (format, args) = format_option(args)?;
+        let mut file;
+        let (out, format) = match args {
+            [] => (out, format.unwrap_or(Format::Json)),
+            [path] => {
+                file = File::create(path).map_err(|err| anyhow!("{path}: {err}"))?;
+                let guessed = if path.ends_with(".csv") {
+                    Format::Csv
+                } else {
+                    Format::Json
+                };
+                (&mut file as &mut dyn Write, format.unwrap_or(guessed))
+            }
+            _ => bail!("history export: too many arguments"),
+        };
+        match format {
+            Format::Json => {
+                serde_json::to_writer_pretty(&mut *out, &self.history)?;
+                writeln!(out)?;
+            }
+            Format::Csv => {
+                let mut writer = csv::Writer::from_writer(out);
+                writer.write_record([
+                    "command", "date", "cwd", "status", "duration", "session", "origin",
+                ])?;
+                for entry in &self.history {
+                    writer.write_record([
+                        entry.command.clone(),
+                        entry.date.to_rfc3339(),
+                        entry.cwd.clone().unwrap_or_default(),
+                        entry
+                            .status
+                            .map(|status| status.to_string())
+                            .unwrap_or_default(),
+                        entry
+                            .duration
+                            .map(|millis| millis.to_string())
+                            .unwrap_or_default(),
+                        entry.session.clone().unwrap_or_default(),
+                        entry.origin.clone().unwrap_or_default(),
+                    ])?;
+                }
+                writer.flush()?;
+            }
+            _ => bail!("history export: only json and csv can be exported"),
+        }
+        Ok(0)
+    }
+
+    /// Runs `history import [--format FORMAT] file...`, which adds the entries of each file
+    /// that are not in the history yet, in date order. The format of a file is guessed
+    /// unless given.
+    pub(super) fn history_import(&mut self, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        let (format, paths) = format_option(args)?;
+        if paths.is_empty() {
+            bail!("history import: file argument required");
+        }
+        let mut seen: HashSet<_> = self.history.iter().map(identity).collect();
+        for path in paths {
+            let entries =
+                import(Path::new(path), format).map_err(|err| anyhow!("history import: {err}"))?;
+            let added: Vec<History> = entries
+                .into_iter()
+                .filter(|entry| seen.insert(identity(entry)))
+                .collect();
+            self.append_history(&added)?;
+            let message = format!("imported {} entries from {path}", added.len());
+            self.history.extend(added);
+            writeln!(out, "{}", message.purple())?;
+        }
+        self.history.sort_by_key(|entry| entry.date);
+        self.sync_line_editor()?;
+        Ok(0)
+    }
+}
+
+/// What tells an imported entry from those already in the history: its command and date
+/// or, for an entry its file gave no date, its command and where it was in that file.
+fn identity(entry: &History) -> (String, Option<String>, Option<DateTime<Local>>) {
+    let date = entry.origin.is_none().then_some(entry.date);
+    (entry.command.clone(), entry.origin.clone(), date)
+}
+
+/// Splits `--format FORMAT` or `--format=FORMAT` off the front of `args`.
+fn format_option(args: &[String]) -> Result<(Option<Format>, &[String])> {
+    match args {
+        [flag, format, rest @ ..] if flag == "--format" => Ok((Some(format.parse()?), rest)),
+        [flag] if flag == "--format" => bail!("--format: option requires an argument"),
+        [flag, rest @ ..] => match flag.strip_prefix("--format=") {
+            Some(format) => Ok((Some(format.parse()?), rest)),
+            None => Ok((None, args)),
+        },
+        [] => Ok((None, args)),
+    }
+}
+
+/// Reads the history in the file at `path`, guessing its format unless `format` is given.
+/// Entries without dates get the time the file was last written.
+fn import(path: &Path, format: Option<Format>) -> Result<Vec<History>> {
+    let bytes = fs::read(path).map_err(|err| anyhow!("{}: {err}", path.display()))?;
+    let path = &fs::canonicalize(path)?;
+    let date = fs::metadata(path)?.modified()?.into();
+    let format = format.unwrap_or_else(|| guess(&String::from_utf8_lossy(&bytes)));
+    let text = if format == Format::Zsh {
+        String::from_utf8_lossy(&unmetafy(&bytes)).into_owned()
+    } else {
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+    match format {
+        Format::Json => {
+            serde_json::from_str(&text).map_err(|err| anyhow!("{}: {err}", path.display()))
+        }
+        Format::Csv => csv::Reader::from_reader(text.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .map_err(|err| anyhow!("{}: {err}", path.display())),
+        Format::Potato => parse(path, &text, date),
+        Format::Bash => Ok(bash(path, &text, date)),
+        Format::Zsh => Ok(zsh(path, &text, date)),
+        Format::Fish => Ok(fish(path, &text, date)),
+    }
+}
+
+/// Guesses the format of `text` from its first line.
+fn guess(text: &str) -> Format {
+    let first = text
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or_default();
+    if first.starts_with('[') {
+        Format::Json
+    } else if first.starts_with("#V") {
+        Format::Potato
+    } else if first.starts_with("command,date") {
+        Format::Csv
+    } else if first.starts_with("- cmd: ") {
+        Format::Fish
+    } else if zsh_line(first).is_some() {
+        Format::Zsh
+    } else {
+        Format::Bash
+    }
+}
+
+/// Bash history: one command per line, each after a `#seconds` line when
+/// `HISTTIMEFORMAT` was set.
+fn bash(path: &Path, text: &str, date: DateTime<Local>) -> Vec<History> {
+    let mut entries = Vec::new();
+    let mut when = None;
+    for line in text.lines() {
+        if let Some(time) = line.strip_prefix('#').and_then(timestamp) {
+            when = Some(time);
+        } else if !line.trim().is_empty() {
+            let command = line.to_owned();
+            entries.push(match when.take() {
+                Some(when) => History::bare(command, when),
+                None => History::undated(command, date, path, entries.len() + 1),
+            });
+        }
+    }
+    entries
+}
+
+/// zsh history. With `EXTENDED_HISTORY` each entry is `: start:seconds;command`. A line
+/// ending in a backslash goes on in the next one.
+fn zsh(path: &Path, text: &str, date: DateTime<Local>) -> Vec<History> {
+    let mut entries = Vec::new();
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        let (mut entry, command) = match zsh_line(line) {
+            Some((when, seconds, command)) => {
+                let entry = History {
+                    duration: Some(seconds * 1000),
+                    ..History::bare(String::new(), when)
+                };
+                (entry, command)
+            }
+            None => (
+                History::undated(String::new(), date, path, entries.len() + 1),
+                line,
+            ),
+        };
+        entry.command = command.to_owned();
+        while entry.command.ends_with('\\') {
+            let Some(next) = lines.next() else {
+                break;
+            };
+            entry.command.pop();
+            entry.command.push('\n');
+            entry.command.push_str(next);
+        }
+        if !entry.command.trim().is_empty() {
+            entries.push(entry);
+        }
+    }
+    entries
+}
+
+/// Splits a `: start:seconds;command` line of zsh's extended history.
+fn zsh_line(line: &str) -> Option<(DateTime<Local>, u64, &str)> {
+    let (head, command) = line.strip_prefix(": ")?.split_once(';')?;
+    let (start, seconds) = head.split_once(':')?;
+    Some((timestamp(start)?, seconds.parse().ok()?, command))
+}
+
+/// Undoes zsh's escaping of special bytes in its history file as 0x83 followed by the
+/// byte xor 32.
+fn unmetafy(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut bytes = bytes.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            0x83 => out.extend(bytes.next().map(|byte| byte ^ 32)),
+            _ => out.push(byte),
+        }
+    }
+    out
+}
+
+/// fish history: a YAML-like list of `- cmd: command` entries, each followed by
+/// `when: seconds`, with `\n` and `\\` escaped in commands.
+fn fish(path: &Path, text: &str, date: DateTime<Local>) -> Vec<History> {
+    let mut entries: Vec<History> = Vec::new();
+    for line in text.lines() {
+        if let Some(command) = line.strip_prefix("- cmd: ") {
+            let position = entries.len() + 1;
+            entries.push(History::undated(unescape(command), date, path, position));
+        } else if let Some(when) = line.trim_start().strip_prefix("when: ").and_then(timestamp) {
+            if let Some(entry) = entries.last_mut() {
+                entry.date = when;
+                entry.origin = None;
+            }
+        }
+    }
+    entries
+}
+
+/// The time `seconds` after the Unix epoch.
+fn timestamp(seconds: &str) -> Option<DateTime<Local>> {
+    Local
+        .timestamp_opt(seconds.trim().parse().ok()?, 0)
+        .single()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    fn history(shell: &mut Shell, args: &[&str]) -> Vec<u8> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let mut out = Vec::new();
+        assert_eq!(shell.builtin_history(&args, &mut out).unwrap(), 0);
+        out
+    }
+
+    /// The commands of `shell`'s history, with the dates of those their file gave one.
+    fn commands(shell: &Shell) -> Vec<(&str, Option<i64>)> {
+        let mut commands: Vec<_> = shell
+            .history
+            .iter()
+            .map(|entry| {
+                let date = entry.origin.is_none().then(|| entry.date.timestamp());
+                (entry.command.as_str(), date)
+            })
+            .collect();
+        commands.sort();
+        commands
+    }
+
+    #[test]
+    fn imports_bash_zsh_and_fish_and_exports_them_again() {
+        let dir = env::temp_dir().join(format!("potato-history-formats-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = |name: &str, text: &[u8]| {
+            let path = dir.join(name);
+            fs::write(&path, text).unwrap();
+            path.to_string_lossy().into_owned()
+        };
+        let bash = file("bash_history", b"ls\n#1700000100\ngit status\n");
+        // The last byte of the dash is one zsh escapes.
+        let zsh = file(
+            "zsh_history",
+            b": 1700000200:3;make\n: 1700000300:0;echo a\\\nb\n: 1700000400:0;echo \xe2\x80\x83\xb4\n",
+        );
+        let fish = file(
+            "fish_history",
+            b"- cmd: echo x\\ny\n  when: 1700000500\n- cmd: pwd\n",
+        );
+
+        let mut shell = Shell::new().unwrap();
+        history(&mut shell, &["import", &bash, &zsh, &fish]);
+        let imported = [
+            ("echo a\nb", Some(1_700_000_300)),
+            ("echo x\ny", Some(1_700_000_500)),
+            ("echo \u{2014}", Some(1_700_000_400)),
+            ("git status", Some(1_700_000_100)),
+            ("ls", None),
+            ("make", Some(1_700_000_200)),
+            ("pwd", None),
+        ];
+        assert_eq!(commands(&shell), imported);
+        let make = shell.history.iter().find(|entry| entry.command == "make");
+        assert_eq!(make.unwrap().duration, Some(3000));
+
+        history(&mut shell, &["import", "--format=fish", &fish]);
+        history(&mut shell, &["import", &bash, &zsh]);
+        assert_eq!(shell.history.len(), imported.len());
+
+        let json = file("export.json", &history(&mut shell, &["export"]));
+        let csv = dir.join("export.csv").to_string_lossy().into_owned();
+        history(&mut shell, &["export", &csv]);
+        for export in [json, csv] {
+            let mut copy = Shell::new().unwrap();
+            history(&mut copy, &["import", &export]);
+            assert_eq!(copy.history, shell.history, "{export}");
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,417 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::{io::Write, iter, path::Path, time::Duration};
+
+use crate::{pattern::Pattern, shell::Shell};
+
+mod expansion;
+mod fc;
+mod file;
+mod formats;
+
+/// First line of the history file format: one JSON entry per line.
+const VERSION: &str = "#V3";
+/// Where history was kept before `history_path`: `history.txt` in the directory the shell
+/// started in, in the rustyline format.
+pub const LEGACY_PATH: &str = "history.txt";
+/// First line of the older rustyline format: one command per line, with `\n` and `\\`
+/// escaped.
+const RUSTYLINE_V2: &str = "#V2";
+
+/// A command run interactively, with where, when and how it ran. Entries migrated from
+/// older files only know their command.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct History {
+    pub command: String,
+    pub date: DateTime<Local>,
+    /// Working directory the command ran in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<i32>,
+    /// How long the command ran, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u64>,
+    /// The shell session that ran the command.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    /// `path:position` of an entry read from a file that gives no date for it, which tells
+    /// it from the same command elsewhere in that file when importing it again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+}
+
+impl History {
+    /// An entry that only knows its command, dated `date`.
+    pub fn bare(command: String, date: DateTime<Local>) -> Self {
+        Self {
+            command,
+            date,
+            cwd: None,
+            status: None,
+            duration: None,
+            session: None,
+            origin: None,
+        }
+    }
+
+    /// The entry at `position` in the file at `path`, which gives no date for it, dated
+    /// `date`.
+    pub fn undated(command: String, date: DateTime<Local>, path: &Path, position: usize) -> Self {
+        Self {
+            origin: Some(format!("{}:{position}", path.display())),
+            ..Self::bare(command, date)
+        }
+    }
+}
+
+/// Parses the text of the history file at `path`. Entries migrated from the rustyline
+/// formats are dated `date`.
+fn parse(path: &Path, text: &str, date: DateTime<Local>) -> Result<Vec<History>> {
+    let mut lines = text.lines();
+    match lines.next() {
+        None => Ok(Vec::new()),
+        // A damaged line costs only its own entry.
+        Some(VERSION) => Ok(lines
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty())
+            .filter_map(|(index, line)| match serde_json::from_str(line) {
+                Ok(entry) => Some(entry),
+                Err(err) => {
+                    let line = index + 2;
+                    let message = format!("history: {}:{line}: {err}, skipped", path.display());
+                    eprintln!("{}", message.red());
+                    None
+                }
+            })
+            .collect()),
+        Some(RUSTYLINE_V2) => Ok(lines
+            .filter(|line| !line.is_empty())
+            .enumerate()
+            .map(|(index, line)| History::undated(unescape(line), date, path, index + 1))
+            .collect()),
+        Some(first) if first.starts_with("#V") => {
+            bail!("{}: unknown history format `{first}`", path.display())
+        }
+        Some(first) => Ok(iter::once(first)
+            .chain(lines)
+            .filter(|line| !line.is_empty())
+            .enumerate()
+            .map(|(index, line)| History::undated(line.to_owned(), date, path, index + 1))
+            .collect()),
+    }
+}
+
+/// Undoes the escaping of a rustyline `#V2` line.
+fn unescape(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                out.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                out.push('\\');
+                chars.next();
+            }
+            (c, _) => out.push(c),
+        }
+    }
+    out
+}
+
+impl Shell {
+    /// Records `command`, started at `date` in `cwd`, which ran for `duration` and left the
+    /// current status.
+    pub fn record_history(
+        &mut self,
+        command: &str,
+        date: DateTime<Local>,
+        cwd: String,
+        duration: Duration,
+    ) {
+        if command.trim().is_empty() {
+            return;
+        }
+        self.history.push(History {
+            cwd: Some(cwd),
+            status: Some(self.status),
+            duration: Some(duration.as_millis() as u64),
+            session: Some(self.session.clone()),
+            ..History::bare(command.to_owned(), date)
+        });
+        if let Err(err) = self.append_history(&self.history[self.history.len() - 1..]) {
+            eprintln!("{}", format!("history: {err}").red());
+        }
+    }
+
+    /// Runs `history [-v] [--since DATE] [--until DATE] [grep PATTERN] [N]`, which lists
+    /// the last `N` entries matching the filters with the numbers `!N` refers to, or
+    /// `history -d`, `history export` and `history import`. With `-v` the date, duration,
+    /// status and directory of each command are shown too.
+    pub fn builtin_history(&mut self, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        match args.first().map(String::as_str) {
+            Some("export") => return self.history_export(&args[1..], out),
+            Some("import") => return self.history_import(&args[1..], out),
+            Some("-d") => return self.history_delete(&args[1..]),
+            _ => {}
+        }
+        let mut verbose = false;
+        let (mut since, mut until, mut pattern, mut count) = (None, None, None, None);
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-v" | "--verbose" => verbose = true,
+                "--since" | "--until" | "grep" => {
+                    let Some(value) = args.next() else {
+                        eprintln!("history: {arg}: argument required");
+                        return Ok(2);
+                    };
+                    match arg.as_str() {
+                        "--since" => since = Some(parse_date(value, false)?),
+                        "--until" => until = Some(parse_date(value, true)?),
+                        _ => {
+                            pattern =
+                                Some(Pattern::new(&format!("*{value}*"), self.options.extglob))
+                        }
+                    }
+                }
+                _ => match arg.parse::<usize>() {
+                    Ok(n) if count.is_none() => count = Some(n),
+                    _ => {
+                        eprintln!("history: {arg}: invalid option");
+                        return Ok(2);
+                    }
+                },
+            }
+        }
+        let entries: Vec<(usize, &History)> = self
+            .history
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| {
+                since.is_none_or(|since| entry.date >= since)
+                    && until.is_none_or(|until| entry.date < until)
+                    && pattern
+                        .as_ref()
+                        .is_none_or(|pattern: &Pattern| pattern.matches(&entry.command))
+            })
+            .collect();
+        let skip = count.map_or(0, |count| entries.len().saturating_sub(count));
+        for (index, entry) in &entries[skip..] {
+            if !verbose {
+                writeln!(out, "{:>5}  {}", index + 1, entry.command.purple())?;
+                continue;
+            }
+            let duration = entry.duration.map_or("-".to_owned(), format_duration);
+            let status = entry
+                .status
+                .map_or("-".to_owned(), |status| status.to_string());
+            writeln!(
+                out,
+                "{:>5}  {}  {:>7}  {:>3}  {}  {}",
+                index + 1,
+                entry.date.format("%Y-%m-%d %H:%M:%S"),
+                duration,
+                status,
+                entry.cwd.as_deref().unwrap_or("-").blue(),
+                entry.command.purple()
+            )?;
+        }
+        Ok(0)
+    }
+
+    /// Runs `history -d N` or `history -d START-END`, which deletes entries by number. A
+    /// negative number counts back from the last entry.
+    fn history_delete(&mut self, args: &[String]) -> Result<i32> {
+        let [spec] = args else {
+            eprintln!("history: -d: position argument required");
+            return Ok(2);
+        };
+        let (first, last) = match spec.get(1..).and_then(|rest| rest.find('-')) {
+            Some(dash) => (&spec[..dash + 1], &spec[dash + 2..]),
+            None => (spec.as_str(), spec.as_str()),
+        };
+        let range = self
+            .history_position(first)
+            .zip(self.history_position(last))
+            .filter(|(first, last)| first <= last);
+        let Some((first, last)) = range else {
+            eprintln!("history: {spec}: history position out of range");
+            return Ok(1);
+        };
+        // Entries can be identical, so each is told from the others by how many copies of
+        // it come before it, in the file as well as here.
+        let removed: Vec<(History, usize)> = (first..=last)
+            .map(|index| {
+                let entry = &self.history[index];
+                let copies = self.history[..index].iter().filter(|e| *e == entry).count();
+                (entry.clone(), copies)
+            })
+            .collect();
+        self.history.drain(first..=last);
+        self.rewrite_history(|entries| {
+            for (entry, copies) in removed.iter().rev() {
+                let position = entries
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, e)| *e == entry)
+                    .nth(*copies)
+                    .map(|(position, _)| position);
+                if let Some(position) = position {
+                    entries.remove(position);
+                }
+            }
+        })?;
+        self.sync_line_editor()?;
+        Ok(0)
+    }
+
+    /// The index of the entry numbered `number`, or counted back from the end if negative.
+    fn history_position(&self, number: &str) -> Option<usize> {
+        let number: isize = number.parse().ok()?;
+        let index = if number < 0 {
+            self.history.len() as isize + number
+        } else {
+            number - 1
+        };
+        usize::try_from(index)
+            .ok()
+            .filter(|&index| index < self.history.len())
+    }
+
+    /// Makes the line editor's history match the shell's after entries were added or
+    /// removed.
+    fn sync_line_editor(&mut self) -> Result<()> {
+        self.rl.clear_history()?;
+        for entry in &self.history {
+            self.rl.add_history_entry(entry.command.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads a date for `--since` or `--until`: `YYYY-MM-DD` with an optional `HH:MM[:SS]`,
+/// RFC 3339, `today`, `yesterday`, or a time ago such as `30m`, `2h`, `3d` or `1w`. A
+/// day without a time means its start, or with `end` the start of the next day.
+fn parse_date(text: &str, end: bool) -> Result<DateTime<Local>> {
+    let now = Local::now();
+    let day = match text {
+        "today" => Some(now.date_naive()),
+        "yesterday" => now.date_naive().pred_opt(),
+        _ => NaiveDate::parse_from_str(text, "%Y-%m-%d").ok(),
+    };
+    let date = if let Some(day) = day {
+        let day = if end { day.succ_opt() } else { Some(day) };
+        day.and_then(|day| local(day.and_time(NaiveTime::MIN)))
+    } else if let Ok(date) = DateTime::parse_from_rfc3339(text) {
+        Some(date.with_timezone(&Local))
+    } else if let Some(date) = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+    {
+        local(date)
+    } else {
+        ago(text).and_then(|ago| now.checked_sub_signed(ago))
+    };
+    date.ok_or_else(|| anyhow!("history: {text}: invalid date"))
+}
+
+fn local(date: NaiveDateTime) -> Option<DateTime<Local>> {
+    Local.from_local_datetime(&date).earliest()
+}
+
+/// Reads a time span such as `30s`, `5m`, `2h`, `3d` or `1w`.
+fn ago(text: &str) -> Option<chrono::Duration> {
+    let unit = match text.chars().next_back()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    let count: u64 = text[..text.len() - 1].parse().ok()?;
+    chrono::Duration::from_std(Duration::from_secs(count.checked_mul(unit)?)).ok()
+}
+
+/// Formats a duration in milliseconds as `850ms`, `12.3s` or `4m05s`.
+fn format_duration(millis: u64) -> String {
+    match millis {
+        0..=999 => format!("{millis}ms"),
+        1000..=59_999 => format!("{:.1}s", millis as f64 / 1000.0),
+        _ => format!("{}m{:02}s", millis / 60_000, millis / 1000 % 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    fn commands(shell: &Shell) -> Vec<&str> {
+        shell
+            .history
+            .iter()
+            .map(|entry| entry.command.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn deletes_entries_by_position() {
+        let dir = env::temp_dir().join(format!("potato-history-delete-{}", process::id()));
+        let path = dir.join("history");
+        // An existing file, so that no older history is brought in.
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&path, format!("{VERSION}\n")).unwrap();
+        let mut shell = Shell::new().unwrap();
+        shell.load_history(path.clone()).unwrap();
+        let date = Local.timestamp_opt(1_700_000_000, 0).unwrap();
+        shell.history = ["a", "b", "a", "c", "d", "e"]
+            .map(|command| History::bare(command.to_owned(), date))
+            .to_vec();
+        shell.append_history(&shell.history).unwrap();
+
+        let mut delete = |spec: &str| {
+            let args = ["-d".to_owned(), spec.to_owned()];
+            shell.builtin_history(&args, &mut Vec::new()).unwrap()
+        };
+        assert_eq!(delete("3"), 0);
+        assert_eq!(delete("-1"), 0);
+        assert_eq!(delete("7"), 1);
+        assert_eq!(delete("3-2"), 1);
+        assert_eq!(delete("2-3"), 0);
+        assert_eq!(commands(&shell), ["a", "d"]);
+
+        let mut reloaded = Shell::new().unwrap();
+        reloaded.load_history(path).unwrap();
+        assert_eq!(commands(&reloaded), ["a", "d"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn loading_skips_damaged_lines() {
+        let path = Path::new("history");
+        let date = Local.timestamp_opt(1_700_000_000, 0).unwrap();
+        let line = |command: &str| {
+            serde_json::to_string(&History::bare(command.to_owned(), date)).unwrap()
+        };
+        let text = format!(
+            "{VERSION}\n{}\n{{\"command\": \"cut\n\n{}\nnot json\n",
+            line("one"),
+            line("two")
+        );
+        let entries = parse(path, &text, date).unwrap();
+        let commands: Vec<&str> = entries.iter().map(|e| e.command.as_str()).collect();
+        assert_eq!(commands, ["one", "two"]);
+
+        let entries = parse(path, "#V2\necho a\\nb\n\nls\n", date).unwrap();
+        assert_eq!(entries[0].command, "echo a\nb");
+        assert_eq!(entries[1].origin.as_deref(), Some("history:2"));
+        assert!(parse(path, "#V9\nls\n", date).is_err());
+    }
+}
@@ -0,0 +1,314 @@
+use anyhow::{anyhow, bail, Result};
+use libc::pid_t;
+use std::{
+    io::{self, Write},
+    os::unix::process::ExitStatusExt,
+    process::ExitStatus,
+};
+
+use crate::{shell::Shell, signals};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    Done,
+}
+
+#[derive(Debug)]
+pub struct Process {
+    pub pid: pid_t,
+    pub name: String,
+    pub status: Option<ExitStatus>,
+    pub stopped: bool,
+}
+
+#[derive(Debug)]
+pub struct Job {
+    pub id: usize,
+    pub pgid: pid_t,
+    pub command: String,
+    pub processes: Vec<Process>,
+}
+
+impl Job {
+    pub fn new(pgid: pid_t, command: String, processes: Vec<Process>) -> Self {
+        Self {
+            id: 0,
+            pgid,
+            command,
+            processes,
+        }
+    }
+
+    pub fn state(&self) -> JobState {
+        if self
+            .processes
+            .iter()
+            .all(|process| process.status.is_some())
+        {
+            JobState::Done
+        } else if self
+            .processes
+            .iter()
+            .any(|process| process.status.is_none() && process.stopped)
+        {
+            JobState::Stopped
+        } else {
+            JobState::Running
+        }
+    }
+
+    /// Collects status changes of the job's processes. When `block` is set this waits until
+    /// every process has exited or one of them has stopped. Without it, processes that are
+    /// not children of this process are skipped.
+    pub fn wait(&mut self, block: bool) -> io::Result<()> {
+        let flags = libc::WUNTRACED | if block { 0 } else { libc::WNOHANG };
+        for process in &mut self.processes {
+            if process.status.is_some() || (process.stopped && !block) {
+                continue;
+            }
+            let mut status = 0;
+            let pid = loop {
+                let pid = unsafe { libc::waitpid(process.pid, &mut status, flags) };
+                if pid != -1 || io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                    break pid;
+                }
+                if signals::take_interrupt() {
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+            };
+            match pid {
+                // A forked copy of the shell, such as the `jobs` of `jobs | cat`, still
+                // lists jobs that are not its children; they are left as they were.
+                -1 if !block && io::Error::last_os_error().raw_os_error() == Some(libc::ECHILD) => {
+                }
+                -1 => return Err(io::Error::last_os_error()),
+                0 => {}
+                _ if libc::WIFSTOPPED(status) => {
+                    process.stopped = true;
+                    if block {
+                        break;
+                    }
+                }
+                _ => process.status = Some(ExitStatus::from_raw(status)),
+            }
+        }
+        Ok(())
+    }
+
+    fn resume(&mut self) {
+        for process in &mut self.processes {
+            process.stopped = false;
+        }
+        unsafe { libc::kill(-self.pgid, libc::SIGCONT) };
+    }
+}
+
+impl Shell {
+    /// Waits for `job` with the terminal handed to it, and takes the terminal back afterwards.
+    pub fn wait_foreground(&mut self, job: &mut Job) -> Result<()> {
+        if self.interactive {
+            unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, job.pgid) };
+        }
+        let result = job.wait(true);
+        if self.interactive {
+            unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, self.pgid) };
+        }
+        Ok(result?)
+    }
+
+    /// Adds `job` to the job table and returns its number.
+    pub fn add_job(&mut self, mut job: Job) -> usize {
+        if job.id == 0 {
+            job.id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        }
+        let id = job.id;
+        self.jobs.push(job);
+        id
+    }
+
+    /// Reports background jobs that finished or stopped since the last prompt.
+    pub fn notify_jobs(&mut self) {
+        let mut out = io::stderr();
+        let last = self.jobs.last().map(|job| job.id);
+        for job in &mut self.jobs {
+            let before = job.state();
+            if job.wait(false).is_err() {
+                continue;
+            }
+            let state = job.state();
+            if state != before && state != JobState::Running {
+                let _ = write_job(&mut out, job, Some(job.id) == last, false);
+            }
+        }
+        self.jobs.retain(|job| job.state() != JobState::Done);
+    }
+
+    fn find_job(&self, spec: Option<&String>) -> Result<usize> {
+        let index = match spec.map(|spec| spec.strip_prefix('%').unwrap_or(spec)) {
+            None | Some("" | "%" | "+") => self.jobs.len().checked_sub(1),
+            Some("-") => self.jobs.len().checked_sub(2),
+            Some(spec) => match spec.parse::<usize>() {
+                Ok(id) => self.jobs.iter().position(|job| job.id == id),
+                Err(_) => self
+                    .jobs
+                    .iter()
+                    .position(|job| job.command.starts_with(spec)),
+            },
+        };
+        index.ok_or_else(|| anyhow!("{}: no such job", spec.map_or("current", |s| s)))
+    }
+
+    pub fn builtin_jobs(&mut self, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        for job in &mut self.jobs {
+            job.wait(false)?;
+        }
+        let last = self.jobs.last().map(|job| job.id);
+        for job in &self.jobs {
+            match args.first().map(String::as_str) {
+                Some("-p") => writeln!(out, "{}", job.pgid)?,
+                Some("-l") => write_job(out, job, Some(job.id) == last, true)?,
+                _ => write_job(out, job, Some(job.id) == last, false)?,
+            }
+        }
+        self.jobs.retain(|job| job.state() != JobState::Done);
+        Ok(0)
+    }
+
+    pub fn builtin_fg(&mut self, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        if !self.interactive {
+            bail!("fg: no job control");
+        }
+        let index = self.find_job(args.first())?;
+        let mut job = self.jobs.remove(index);
+        writeln!(out, "{}", job.command)?;
+        out.flush()?;
+        job.resume();
+        self.wait_foreground(&mut job)?;
+        Ok(self.finish_foreground(job))
+    }
+
+    pub fn builtin_bg(&mut self, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        if !self.interactive {
+            bail!("bg: no job control");
+        }
+        let index = self.find_job(args.first())?;
+        let job = &mut self.jobs[index];
+        job.resume();
+        writeln!(out, "[{}]+ {} &", job.id, job.command)?;
+        Ok(0)
+    }
+
+    pub fn builtin_disown(&mut self, args: &[String]) -> Result<i32> {
+        if args.first().is_some_and(|arg| arg == "-a") {
+            self.jobs.clear();
+        } else {
+            let index = self.find_job(args.first())?;
+            self.jobs.remove(index);
+        }
+        Ok(0)
+    }
+
+    pub fn builtin_wait(&mut self, args: &[String]) -> Result<i32> {
+        let indexes = if args.is_empty() {
+            (0..self.jobs.len()).collect()
+        } else {
+            args.iter()
+                .map(|arg| match arg.parse::<pid_t>() {
+                    Ok(pid) => self
+                        .jobs
+                        .iter()
+                        .position(|job| job.processes.iter().any(|process| process.pid == pid))
+                        .ok_or_else(|| anyhow!("wait: pid {pid} is not a child of this shell")),
+                    Err(_) => self.find_job(Some(arg)),
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+        let mut status = 0;
+        for index in indexes {
+            let job = &mut self.jobs[index];
+            if job.state() != JobState::Stopped {
+                match job.wait(true) {
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                        eprintln!();
+                        return Ok(130);
+                    }
+                    result => result?,
+                }
+                status = job_status(job);
+            }
+        }
+        self.jobs.retain(|job| job.state() != JobState::Done);
+        Ok(status)
+    }
+
+    /// Sets the job aside if it was stopped, and returns the status it leaves behind.
+    pub fn finish_foreground(&mut self, job: Job) -> i32 {
+        if job.state() == JobState::Stopped {
+            let id = self.add_job(job);
+            let job = self.jobs.iter().find(|job| job.id == id).unwrap();
+            eprintln!();
+            let _ = write_job(&mut io::stderr(), job, true, false);
+            return 128 + libc::SIGTSTP;
+        }
+        job_status(&job)
+    }
+}
+
+/// The status of the last process of a job, like the status of a pipeline.
+pub fn job_status(job: &Job) -> i32 {
+    job.processes
+        .last()
+        .and_then(|process| process.status)
+        .map_or(0, exit_code)
+}
+
+pub fn exit_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1)
+}
+
+fn write_job(out: &mut dyn Write, job: &Job, current: bool, long: bool) -> io::Result<()> {
+    let state = match job.state() {
+        JobState::Running => "Running".to_owned(),
+        JobState::Stopped => "Stopped".to_owned(),
+        JobState::Done => match job_status(job) {
+            0 => "Done".to_owned(),
+            status => format!("Exit {status}"),
+        },
+    };
+    let marker = if current { '+' } else { ' ' };
+    if long {
+        writeln!(
+            out,
+            "[{}]{} {} {:<10} {}",
+            job.id, marker, job.pgid, state, job.command
+        )
+    } else {
+        writeln!(out, "[{}]{} {:<10} {}", job.id, marker, state, job.command)
+    }
+}
+
+impl Shell {
+    /// Puts an interactive shell in its own process group in charge of the terminal.
+    pub fn init_job_control(&mut self) {
+        self.interactive = unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+        if !self.interactive {
+            return;
+        }
+        unsafe {
+            while libc::tcgetpgrp(libc::STDIN_FILENO) != libc::getpgrp() {
+                libc::kill(-libc::getpgrp(), libc::SIGTTIN);
+            }
+        }
+        signals::ignore_interactive();
+        unsafe {
+            self.pgid = libc::getpid();
+            libc::setpgid(0, self.pgid);
+            libc::tcsetpgrp(libc::STDIN_FILENO, self.pgid);
+        }
+    }
+}
@@ -1,81 +1,232 @@
-use anyhow::Result;
-use chrono::{DateTime, Local};
+mod alias;
+mod arith;
+mod brace;
+mod builtins;
+mod compound;
+mod exec;
+mod expand;
+mod functions;
+mod glob;
+mod history;
+mod jobs;
+mod options;
+mod parser;
+mod pattern;
+mod redirect;
+mod script;
+mod shell;
+mod signals;
+mod vars;
+
+use anyhow::{bail, Result};
+use builtins::print_help;
+use chrono::Local;
 use colored::Colorize;
-use rustyline::{error::ReadlineError, history::FileHistory, DefaultEditor, Editor};
-use serde::{Deserialize, Serialize};
+use rustyline::error::ReadlineError;
+use script::StdinLines;
+use shell::Shell;
 use std::{
-    env::{self},
-    fs::{self, File},
-    path::Path,
-    process::{Child, Command, Stdio},
-    str::FromStr,
+    env, fs, io, mem,
+    path::{Path, PathBuf},
+    process,
+    time::Instant,
 };
 
-#[derive(Debug)]
-enum Builtin {
-    History,
-    Cd,
-    Pwd,
-    Clear,
-    Exit,
-    ClearHistory,
-    Help,
-    Other(String),
+/// What the shell was started to do.
+enum Mode {
+    Interactive,
+    /// `-c command`
+    Command(String),
+    Script(String),
+    /// Commands piped into standard input.
+    Stdin,
 }
 
-impl FromStr for Builtin {
-    type Err = ();
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "history" => Ok(Builtin::History),
-            "cd" => Ok(Builtin::Cd),
-            "pwd" => Ok(Builtin::Pwd),
-            "clear" => Ok(Builtin::Clear),
-            "exit" => Ok(Builtin::Exit),
-            "help" => Ok(Builtin::Help),
-            "clearHistory" => Ok(Builtin::ClearHistory),
-            _ => Ok(Builtin::Other(s.to_owned())),
+/// How the shell was started.
+struct Args {
+    mode: Mode,
+    /// `-l`, `--login` or a `$0` starting with `-`: the profile runs first.
+    login: bool,
+    /// `--norc`: no rc file runs.
+    norc: bool,
+    /// `--rcfile FILE`: FILE runs instead of `~/.potatorc`.
+    rcfile: Option<String>,
+}
+
+fn main() -> Result<()> {
+    let mut shell = Shell::new()?;
+    let args = match parse_args(&mut shell, env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}", format!("potato-shell: {err}").red());
+            process::exit(2);
         }
+    };
+    if let Mode::Interactive = args.mode {
+        shell.init_job_control();
     }
+    run_startup_files(&mut shell, &args);
+    if let Some(code) = shell.exit_code {
+        process::exit(code);
+    }
+    match args.mode {
+        Mode::Interactive => run_interactive(&mut shell)?,
+        Mode::Command(command) => {
+            let lines = command.lines().map(|line| Ok(line.to_owned()));
+            shell.run_lines("-c", lines)?;
+        }
+        Mode::Script(path) => match fs::read_to_string(&path) {
+            Ok(script) => {
+                let lines = script.lines().map(|line| Ok(line.to_owned()));
+                shell.run_lines(&path, lines)?;
+            }
+            Err(err) => {
+                eprintln!("{}", format!("potato-shell: {path}: {err}").red());
+                process::exit(127);
+            }
+        },
+        Mode::Stdin => {
+            let name = shell.arg0.clone();
+            shell.run_lines(&name, StdinLines::new()?)?;
+        }
+    }
+    process::exit(shell.exit_code.unwrap_or(shell.status));
 }
 
-#[derive(Debug)]
-enum FilePipe<'a> {
-    ReadFile(&'a str),
-    WriteFile(&'a str),
+/// Reads the command line: options, then `-c command [name [args...]]`, `script [args...]`,
+/// or nothing to read commands from standard input. Sets `$0` and the positional
+/// parameters.
+fn parse_args(shell: &mut Shell, mut args: impl Iterator<Item = String>) -> Result<Args> {
+    let mut login = shell.arg0.starts_with('-');
+    let mut norc = false;
+    let mut rcfile = None;
+    let mode = loop {
+        match args.next() {
+            Some(arg) if arg == "-l" || arg == "--login" => login = true,
+            Some(arg) if arg == "--norc" => norc = true,
+            Some(arg) if arg == "--rcfile" => {
+                let Some(path) = args.next() else {
+                    bail!("--rcfile: option requires an argument");
+                };
+                rcfile = Some(path);
+            }
+            Some(arg) if arg == "-c" => {
+                let Some(command) = args.next() else {
+                    bail!("-c: option requires an argument");
+                };
+                if let Some(name) = args.next() {
+                    shell.arg0 = name;
+                }
+                break Mode::Command(command);
+            }
+            Some(arg) if arg == "--" => {
+                break match args.next() {
+                    Some(path) => Mode::Script(path),
+                    None => Mode::Stdin,
+                }
+            }
+            Some(arg) if arg.starts_with('-') => bail!("{arg}: invalid option"),
+            Some(path) => break Mode::Script(path),
+            None if unsafe { libc::isatty(libc::STDIN_FILENO) } == 1 => break Mode::Interactive,
+            None => break Mode::Stdin,
+        }
+    };
+    if let Mode::Script(path) = &mode {
+        shell.arg0 = path.clone();
+    }
+    shell.positional = args.collect();
+    Ok(Args {
+        mode,
+        login,
+        norc,
+        rcfile,
+    })
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct History {
-    command: String,
-    date: DateTime<Local>,
+/// Runs `~/.potato_profile` for a login shell, or the rc file for an interactive one.
+fn run_startup_files(shell: &mut Shell, args: &Args) {
+    let home = shell.vars.get("HOME").map(PathBuf::from);
+    if args.login {
+        if let Some(home) = home {
+            shell.run_startup_file(&home.join(".potato_profile"));
+        }
+    } else if matches!(args.mode, Mode::Interactive) && !args.norc {
+        match &args.rcfile {
+            Some(path) => {
+                if let Err(err) = shell.source_file(path, &[]) {
+                    eprintln!("{}", err.to_string().red());
+                }
+            }
+            None => {
+                if let Some(home) = home {
+                    shell.run_startup_file(&home.join(".potatorc"));
+                }
+            }
+        }
+    }
 }
 
-fn main() -> Result<()> {
-    let mut rl = DefaultEditor::new()?;
-
-    if rl.load_history("history.txt").is_err() {
+/// Reads and runs commands from the terminal until `exit` or end of input.
+fn run_interactive(shell: &mut Shell) -> Result<()> {
+    let path = shell.history_path();
+    let known =
+        path.as_ref().is_some_and(|path| path.exists()) || Path::new(history::LEGACY_PATH).exists();
+    if !known {
         println!("{}", "Wellcome to potao shell".yellow());
-        print_help();
+        print_help(&mut io::stdout())?;
+    }
+    if let Some(path) = path {
+        if let Err(err) = shell.load_history(path) {
+            eprintln!("{}", format!("history: {err}").red());
+        }
     }
-    let mut currct_path = "/".to_owned();
     loop {
-        let readline = rl.readline(&format!(
-            "{} {}{}",
-            "$".green(),
-            currct_path.green(),
-            "/ : ".green()
-        ));
-
-        match readline {
+        shell.notify_jobs();
+        if shell.options.sharehistory {
+            if let Err(err) = shell.read_shared_history() {
+                eprintln!("{}", format!("history: {err}").red());
+            }
+        }
+        match read_command(shell) {
             Ok(line) => {
-                rl.add_history_entry(&line)?;
-                if let Err(err) = handel_command(line, &mut rl, &mut currct_path) {
-                    eprintln!("{}", err.to_string().red());
+                let (line, run) = match shell.expand_history(&line) {
+                    Ok(None) => (line, true),
+                    Ok(Some(expanded)) => {
+                        println!("{}", expanded.line);
+                        (expanded.line, !expanded.print_only)
+                    }
+                    Err(err) => {
+                        shell.status = 1;
+                        eprintln!("{}", err.to_string().red());
+                        continue;
+                    }
+                };
+                shell.rl.add_history_entry(&line)?;
+                let (date, started) = (Local::now(), Instant::now());
+                let cwd = env::current_dir().map_or_else(
+                    |_| shell.current_path.clone(),
+                    |dir| dir.to_string_lossy().into_owned(),
+                );
+                if run {
+                    if let Err(err) = shell.handel_command(&line) {
+                        shell.status = 1;
+                        eprintln!("{}", err.to_string().red());
+                    }
+                }
+                if !mem::take(&mut shell.fc_ran) {
+                    shell.record_history(&line, date, cwd, started.elapsed());
+                }
+                if shell.exit_code.is_some() {
+                    break;
                 }
             }
             Err(ReadlineError::Interrupted) => {
-                println!("{}", "CTRL-C, Bye".blue());
+                shell.status = 130;
+            }
+            Err(ReadlineError::Eof) => {
+                if shell.interactive {
+                    println!("{}", "Bye".blue());
+                }
                 break;
             }
             Err(err) => {
@@ -84,114 +235,21 @@ fn main() -> Result<()> {
             }
         }
     }
-    rl.save_history("./history.txt")?;
     Ok(())
 }
 
-fn handel_command(
-    input: String,
-    rl: &mut Editor<(), FileHistory>,
-    curruct_path: &mut String,
-) -> Result<()> {
-    let mut commands = input.trim().split(" | ").peekable();
-    let mut previous_command = None;
-
-    while let Some(command) = commands.next() {
-        let (file, mut parts) = if command.contains("<<") {
-            let mut parts = command.split("<<");
-            let com = parts.next().unwrap().split_whitespace();
-            (Some(FilePipe::ReadFile(parts.next().unwrap().trim())), com)
-        } else if command.contains(">>") {
-            let mut parts = command.split(">>");
-            let com = parts.next().unwrap().split_whitespace();
-            (Some(FilePipe::WriteFile(parts.next().unwrap().trim())), com)
-        } else {
-            (None, command.split_whitespace())
-        };
-        let command = parts.next().unwrap();
-        let args = parts;
-
-        match Builtin::from_str(command).unwrap() {
-            Builtin::History => rl
-                .history()
-                .iter()
-                .for_each(|history| println!("{}", history.purple())),
-            Builtin::Cd => {
-                let new_dir = args.peekable().peek().map_or("/", |x| *x);
-                let root = Path::new(new_dir);
-                if let Err(e) = env::set_current_dir(&root) {
-                    eprintln!("{}", e);
-                } else {
-                    *curruct_path = new_dir.to_owned();
-                }
-                previous_command = None;
-            }
-            Builtin::Pwd => println!("{}", curruct_path.purple()),
-            Builtin::Clear => rl.clear_screen()?,
-            Builtin::Exit => break,
-            Builtin::ClearHistory => {
-                rl.clear_history()?;
-                fs::write("./history.txt", "#V2\n")?;
-                println!("{}", "history cleared".purple());
-            }
-            Builtin::Other(command) => {
-                let stdin = if let Some(FilePipe::ReadFile(file)) = file {
-                    Stdio::from(File::open(file)?)
-                } else {
-                    previous_command.map_or(Stdio::inherit(), |output: Child| {
-                        Stdio::from(output.stdout.unwrap())
-                    })
-                };
-
-                let stdout = if let Some(FilePipe::WriteFile(file)) = file {
-                    Stdio::from(File::options().write(true).create(true).open(file)?)
-                } else if commands.peek().is_some() {
-                    Stdio::piped()
-                } else {
-                    Stdio::inherit()
-                };
-
-                let output = Command::new(command)
-                    .args(args)
-                    .stdin(stdin)
-                    .stdout(stdout)
-                    .spawn();
-
-                match output {
-                    Ok(output) => {
-                        previous_command = Some(output);
-                    }
-                    Err(e) => {
-                        previous_command = None;
-                        eprintln!("{}{}", "command failed to start : ".red(), e);
-                    }
-                };
-            }
-            Builtin::Help => print_help(),
-        }
-    }
-
-    if let Some(mut final_command) = previous_command {
-        final_command.wait()?;
+/// Reads one command, asking for continuation lines while it is incomplete.
+fn read_command(shell: &mut Shell) -> rustyline::Result<String> {
+    let mut input = shell.rl.readline(&format!(
+        "{} {}{}",
+        "$".green(),
+        shell.current_path.green(),
+        "/ : ".green()
+    ))?;
+    while parser::parse_with_aliases(&input, &shell.aliases).is_err_and(|err| err.incomplete) {
+        let line = shell.rl.readline(&"> ".green().to_string())?;
+        input.push('\n');
+        input.push_str(&line);
     }
-
-    Ok(())
-}
-
-fn print_help() {
-    println!(
-        "{} \n {}",
-        r#" these are the Builtin commands that you can use
-    - history: show your commands history
-    - cd: change directory 
-    - pwd: see  dirctgoury you currently on
-    - clear: clear the screen
-    - exit: exit the potato shell
-    - clearhistory: clear you history
-    - help: see help againg :)
-
-            "#
-        .purple(),
-        r#"use '|' for piping and for file chaing use "<<" and ">>" "#.blue()
-    )
+    Ok(input)
 }
@@ -0,0 +1,39 @@
+/// Shell options set with `shopt`.
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Patterns match names starting with `.` without an explicit `.`.
+    pub dotglob: bool,
+    /// `?(...)`, `*(...)`, `+(...)`, `@(...)` and `!(...)` are patterns.
+    pub extglob: bool,
+    /// A pattern that matches nothing is an error.
+    pub failglob: bool,
+    /// `**` matches directories recursively.
+    pub globstar: bool,
+    /// A pattern that matches nothing is removed.
+    pub nullglob: bool,
+    /// Commands other interactive shells add to the history show up before each prompt.
+    pub sharehistory: bool,
+}
+
+impl Options {
+    pub const NAMES: [&'static str; 6] = [
+        "dotglob",
+        "extglob",
+        "failglob",
+        "globstar",
+        "nullglob",
+        "sharehistory",
+    ];
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        Some(match name {
+            "dotglob" => &mut self.dotglob,
+            "extglob" => &mut self.extglob,
+            "failglob" => &mut self.failglob,
+            "globstar" => &mut self.globstar,
+            "nullglob" => &mut self.nullglob,
+            "sharehistory" => &mut self.sharehistory,
+            _ => return None,
+        })
+    }
+}
@@ -0,0 +1,360 @@
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WordPart {
+    Literal(String),
+    Quoted(String),
+    DoubleQuoted(Vec<WordPart>),
+    Param(ParamExp),
+    /// `$(...)` or `` `...` ``
+    Command(List),
+    /// `$((...))`
+    Arith(Word),
+}
+
+/// A parameter expansion, `$name` or `${name...}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamExp {
+    pub name: String,
+    pub op: ParamOp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamOp {
+    None,
+    /// `${#name}`
+    Length,
+    /// `${name-word}`, `${name=word}`, `${name?word}` and `${name+word}`. With a colon an
+    /// empty value counts as unset.
+    Test {
+        kind: TestKind,
+        colon: bool,
+        word: Word,
+    },
+    /// `${name#pattern}` and `${name##pattern}`
+    RemovePrefix {
+        longest: bool,
+        pattern: Word,
+    },
+    /// `${name%pattern}` and `${name%%pattern}`
+    RemoveSuffix {
+        longest: bool,
+        pattern: Word,
+    },
+    /// `${name/pattern/string}` and its `//`, `/#` and `/%` forms
+    Replace {
+        mode: ReplaceMode,
+        pattern: Word,
+        replacement: Word,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestKind {
+    /// `-`
+    Default,
+    /// `=`
+    Assign,
+    /// `?`
+    Error,
+    /// `+`
+    Alternative,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplaceMode {
+    First,
+    All,
+    Prefix,
+    Suffix,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+}
+
+impl Word {
+    /// The word with all quoting removed.
+    pub fn unquoted(&self) -> String {
+        fn push(parts: &[WordPart], out: &mut String) {
+            for part in parts {
+                match part {
+                    WordPart::Literal(text) | WordPart::Quoted(text) => out.push_str(text),
+                    WordPart::DoubleQuoted(parts) => push(parts, out),
+                    WordPart::Param(param) => param.write(out),
+                    WordPart::Command(list) => *out += &format!("$({})", list.text()),
+                    WordPart::Arith(expr) => *out += &format!("$(({}))", expr.unquoted()),
+                }
+            }
+        }
+        let mut out = String::new();
+        push(&self.parts, &mut out);
+        out
+    }
+
+    /// Splits a `NAME=value` word into an assignment.
+    pub fn as_assignment(&self) -> Option<Assignment> {
+        let WordPart::Literal(first) = self.parts.first()? else {
+            return None;
+        };
+        let (name, rest) = first.split_once('=')?;
+        if !is_name(name) {
+            return None;
+        }
+        let mut parts = Vec::new();
+        if !rest.is_empty() {
+            parts.push(WordPart::Literal(rest.to_owned()));
+        }
+        parts.extend_from_slice(&self.parts[1..]);
+        Some(Assignment {
+            name: name.to_owned(),
+            value: Word { parts },
+        })
+    }
+}
+
+impl ParamExp {
+    /// Whether this is a plain `$@` or `${name[@]}`, which give one field per element.
+    pub fn is_all_args(&self) -> bool {
+        (self.name == "@" || self.name.ends_with("[@]")) && self.op == ParamOp::None
+    }
+
+    fn write(&self, out: &mut String) {
+        let name = &self.name;
+        match &self.op {
+            ParamOp::None
+                if name.ends_with(']')
+                    || name.len() > 1 && name.bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                *out += &format!("${{{name}}}")
+            }
+            ParamOp::None => *out += &format!("${name}"),
+            ParamOp::Length => *out += &format!("${{#{name}}}"),
+            ParamOp::Test { kind, colon, word } => {
+                let op = match kind {
+                    TestKind::Default => '-',
+                    TestKind::Assign => '=',
+                    TestKind::Error => '?',
+                    TestKind::Alternative => '+',
+                };
+                let colon = if *colon { ":" } else { "" };
+                *out += &format!("${{{name}{colon}{op}{}}}", word.unquoted());
+            }
+            ParamOp::RemovePrefix { longest, pattern } => {
+                let op = if *longest { "##" } else { "#" };
+                *out += &format!("${{{name}{op}{}}}", pattern.unquoted());
+            }
+            ParamOp::RemoveSuffix { longest, pattern } => {
+                let op = if *longest { "%%" } else { "%" };
+                *out += &format!("${{{name}{op}{}}}", pattern.unquoted());
+            }
+            ParamOp::Replace {
+                mode,
+                pattern,
+                replacement,
+            } => {
+                let op = match mode {
+                    ReplaceMode::First => "/",
+                    ReplaceMode::All => "//",
+                    ReplaceMode::Prefix => "/#",
+                    ReplaceMode::Suffix => "/%",
+                };
+                *out += &format!(
+                    "${{{name}{op}{}/{}}}",
+                    pattern.unquoted(),
+                    replacement.unquoted()
+                );
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Word,
+}
+
+/// Whether `s` is a valid variable name.
+pub fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilePipe {
+    /// `<`
+    Read,
+    /// `>` and `>|`
+    Write,
+    /// `>>`
+    Append,
+    /// `<>`
+    ReadWrite,
+    /// `&>`, or `>&` followed by a file name
+    WriteAll,
+    /// `&>>`
+    AppendAll,
+    /// `<&` and `>&`, the target is a descriptor or `-`
+    Dup,
+    /// `<<` and `<<-`, the target is the body
+    HereDoc,
+    /// `<<<`
+    HereString,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    pub fd: i32,
+    pub kind: FilePipe,
+    pub target: Word,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimpleCommand {
+    pub assignments: Vec<Assignment>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Simple(SimpleCommand),
+    Compound(CompoundCommand, Vec<Redirect>),
+    /// `name() compound-command` or `function name compound-command`
+    Function(Function),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub name: String,
+    /// The compound command with its redirections, shared with the shell's function table.
+    pub body: Rc<Command>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompoundCommand {
+    /// `{ list; }`
+    Group(List),
+    /// `((expression))`
+    Arith(Word),
+    /// `if list; then list; [elif list; then list;]... [else list;] fi`
+    If {
+        branches: Vec<(List, List)>,
+        otherwise: Option<List>,
+    },
+    /// `while list; do list; done`, or `until` when `until` is set
+    While {
+        until: bool,
+        condition: List,
+        body: List,
+    },
+    /// `for name [in words]; do list; done`
+    For {
+        name: String,
+        words: Option<Vec<Word>>,
+        body: List,
+    },
+    /// `for ((init; condition; step)); do list; done`
+    ArithFor {
+        init: Word,
+        condition: Word,
+        step: Word,
+        body: List,
+    },
+    /// `case word in pattern[|pattern]) list;; ... esac`
+    Case { word: Word, items: Vec<CaseItem> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseItem {
+    pub patterns: Vec<Word>,
+    pub body: List,
+}
+
+impl Command {
+    /// A short description used in job and error messages.
+    pub fn name(&self) -> String {
+        match self {
+            Command::Simple(command) => command
+                .words
+                .first()
+                .map(Word::unquoted)
+                .unwrap_or_default(),
+            Command::Function(function) => function.name.clone(),
+            Command::Compound(CompoundCommand::Group(_), _) => "{".to_owned(),
+            Command::Compound(CompoundCommand::Arith(expr), _) => {
+                format!("(({}))", expr.unquoted())
+            }
+            Command::Compound(CompoundCommand::If { .. }, _) => "if".to_owned(),
+            Command::Compound(CompoundCommand::While { until: false, .. }, _) => "while".to_owned(),
+            Command::Compound(CompoundCommand::While { until: true, .. }, _) => "until".to_owned(),
+            Command::Compound(
+                CompoundCommand::For { .. } | CompoundCommand::ArithFor { .. },
+                _,
+            ) => "for".to_owned(),
+            Command::Compound(CompoundCommand::Case { .. }, _) => "case".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+    /// The source text, used to describe jobs.
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+    pub background: bool,
+}
+
+impl AndOr {
+    pub fn text(&self) -> String {
+        let mut text = self.first.text.clone();
+        for (connector, pipeline) in &self.rest {
+            text.push_str(match connector {
+                Connector::And => " && ",
+                Connector::Or => " || ",
+            });
+            text.push_str(&pipeline.text);
+        }
+        text
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct List {
+    pub items: Vec<AndOr>,
+}
+
+impl List {
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for (i, and_or) in self.items.iter().enumerate() {
+            if i > 0 {
+                text.push(' ');
+            }
+            text.push_str(&and_or.text());
+            if and_or.background {
+                text.push_str(" &");
+            } else if i + 1 < self.items.len() {
+                text.push(';');
+            }
+        }
+        text
+    }
+}
@@ -0,0 +1,868 @@
+use super::{
+    ast::{is_name, ParamExp, ParamOp, ReplaceMode, TestKind, Word, WordPart},
+    parse_substitution, parse_with_aliases, Aliases, ParseError, Span, NO_ALIASES,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Pipe,
+    OrIf,
+    Amp,
+    AndIf,
+    Semi,
+    Less,
+    DLess,
+    Great,
+    DGreat,
+    LessAnd,
+    GreatAnd,
+    LessGreat,
+    Clobber,
+    AndGreat,
+    AndDGreat,
+    DLessDash,
+    TLess,
+    LParen,
+    RParen,
+    DLParen,
+    DSemi,
+}
+
+impl Op {
+    const ALL: [(&'static str, Op); 21] = [
+        ("&>>", Op::AndDGreat),
+        ("<<-", Op::DLessDash),
+        ("<<<", Op::TLess),
+        ("||", Op::OrIf),
+        ("&&", Op::AndIf),
+        ("<<", Op::DLess),
+        (">>", Op::DGreat),
+        ("<&", Op::LessAnd),
+        (">&", Op::GreatAnd),
+        ("<>", Op::LessGreat),
+        (">|", Op::Clobber),
+        ("((", Op::DLParen),
+        (";;", Op::DSemi),
+        ("&>", Op::AndGreat),
+        ("|", Op::Pipe),
+        ("&", Op::Amp),
+        (";", Op::Semi),
+        ("<", Op::Less),
+        (">", Op::Great),
+        ("(", Op::LParen),
+        (")", Op::RParen),
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        Op::ALL.iter().find(|(_, op)| *op == self).unwrap().0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenKind {
+    Word(Word),
+    IoNumber(i32),
+    Op(Op),
+    Newline,
+    Eof,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+pub struct Lexer<'a> {
+    src: &'a str,
+    pos: usize,
+    /// Where the next here-document body starts once the current line has been read.
+    heredoc_end: Option<usize>,
+    /// The aliases of commands in substitutions.
+    aliases: &'a Aliases,
+}
+
+fn is_meta(c: char) -> bool {
+    matches!(
+        c,
+        ' ' | '\t' | '\n' | '|' | '&' | ';' | '<' | '>' | '(' | ')'
+    )
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(src: &'a str) -> Self {
+        Self::at(src, 0, &NO_ALIASES)
+    }
+
+    /// A lexer that starts reading `src` at byte `pos`.
+    pub fn at(src: &'a str, pos: usize, aliases: &'a Aliases) -> Self {
+        Self {
+            src,
+            pos,
+            heredoc_end: None,
+            aliases,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.src[self.pos..].chars().nth(n)
+    }
+
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_blanks(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ' | '\t') => {
+                    self.bump();
+                }
+                Some('\\') if self.peek_nth(1) == Some('\n') => {
+                    self.pos += 2;
+                }
+                Some('#') => {
+                    while !matches!(self.peek(), None | Some('\n')) {
+                        self.bump();
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
+    pub fn next_token(&mut self) -> Result<Token, ParseError> {
+        self.skip_blanks();
+        let start = self.pos;
+        let kind = match self.peek() {
+            None => TokenKind::Eof,
+            Some('\n') => {
+                self.bump();
+                if let Some(end) = self.heredoc_end.take() {
+                    self.pos = end;
+                }
+                TokenKind::Newline
+            }
+            Some(_) => {
+                let rest = &self.src[self.pos..];
+                let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(0);
+                if digits > 0 && rest[digits..].starts_with(['<', '>']) {
+                    if let Ok(fd) = rest[..digits].parse() {
+                        self.pos += digits;
+                        return Ok(Token {
+                            kind: TokenKind::IoNumber(fd),
+                            span: Span::new(start, self.pos),
+                        });
+                    }
+                }
+                if let Some((text, op)) = Op::ALL.iter().find(|(text, _)| rest.starts_with(text)) {
+                    self.pos += text.len();
+                    TokenKind::Op(*op)
+                } else {
+                    TokenKind::Word(self.word()?)
+                }
+            }
+        };
+        Ok(Token {
+            kind,
+            span: Span::new(start, self.pos),
+        })
+    }
+
+    fn word(&mut self) -> Result<Word, ParseError> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        while let Some(c) = self.peek() {
+            if is_meta(c) {
+                break;
+            }
+            let start = self.pos;
+            self.bump();
+            match c {
+                '\\' => match self.bump() {
+                    Some('\n') => {}
+                    Some(c) => {
+                        flush(&mut literal, &mut parts);
+                        parts.push(WordPart::Quoted(c.to_string()));
+                    }
+                    None => {
+                        return Err(ParseError::incomplete(
+                            "unexpected end of input after `\\`",
+                            Span::new(start, self.pos),
+                        ))
+                    }
+                },
+                '\'' => {
+                    flush(&mut literal, &mut parts);
+                    parts.push(self.single_quoted(start)?);
+                }
+                '"' => {
+                    flush(&mut literal, &mut parts);
+                    parts.push(WordPart::DoubleQuoted(self.quoted_text(start, false)?));
+                }
+                '`' => {
+                    flush(&mut literal, &mut parts);
+                    parts.push(self.backquoted(start, false)?);
+                }
+                '?' | '*' | '+' | '@' | '!' if self.peek() == Some('(') => {
+                    literal.push(c);
+                    self.extglob_group(start, &mut literal)?;
+                }
+                '$' => match self.dollar(start, false)? {
+                    Some(part) => {
+                        flush(&mut literal, &mut parts);
+                        parts.push(part);
+                    }
+                    None => literal.push('$'),
+                },
+                c => literal.push(c),
+            }
+        }
+        flush(&mut literal, &mut parts);
+        Ok(Word { parts })
+    }
+
+    /// Parses the rest of a single-quoted string whose `'` started at `start`.
+    fn single_quoted(&mut self, start: usize) -> Result<WordPart, ParseError> {
+        let end = self.src[self.pos..].find('\'').ok_or_else(|| {
+            ParseError::incomplete(
+                "unterminated single quote",
+                Span::new(start, self.src.len()),
+            )
+        })?;
+        let text = self.src[self.pos..self.pos + end].to_owned();
+        self.pos += end + 1;
+        Ok(WordPart::Quoted(text))
+    }
+
+    /// Reads an extended glob group such as the `(*.log|*.tmp)` of `!(*.log|*.tmp)` into
+    /// `literal`, so its parentheses are not taken as operators.
+    fn extglob_group(&mut self, start: usize, literal: &mut String) -> Result<(), ParseError> {
+        let mut depth = 0;
+        loop {
+            let c = self.bump().ok_or_else(|| {
+                ParseError::incomplete("unterminated `(`", Span::new(start, self.pos))
+            })?;
+            literal.push(c);
+            match c {
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                '\\' => literal.extend(self.bump()),
+                _ => {}
+            }
+        }
+    }
+
+    /// Parses the inside of a double-quoted string, or a whole here-document body, which
+    /// has no closing quote and keeps `"` and `\"` as they are.
+    fn quoted_text(&mut self, start: usize, heredoc: bool) -> Result<Vec<WordPart>, ParseError> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        loop {
+            match self.bump() {
+                None if heredoc => break,
+                None => {
+                    return Err(ParseError::incomplete(
+                        "unterminated double quote",
+                        Span::new(start, self.pos),
+                    ))
+                }
+                Some('"') if !heredoc => break,
+                Some('\\') => match self.peek() {
+                    Some('\n') => {
+                        self.bump();
+                    }
+                    Some(c @ ('$' | '`' | '\\')) => {
+                        self.bump();
+                        literal.push(c);
+                    }
+                    Some('"') if !heredoc => {
+                        self.bump();
+                        literal.push('"');
+                    }
+                    _ => literal.push('\\'),
+                },
+                Some('`') => {
+                    flush(&mut literal, &mut parts);
+                    parts.push(self.backquoted(self.pos - 1, true)?);
+                }
+                Some('$') => match self.dollar(self.pos - 1, true)? {
+                    Some(part) => {
+                        flush(&mut literal, &mut parts);
+                        parts.push(part);
+                    }
+                    None => literal.push('$'),
+                },
+                Some(c) => literal.push(c),
+            }
+        }
+        flush(&mut literal, &mut parts);
+        Ok(parts)
+    }
+
+    /// Reads the body of a here-document whose operator is on the current line. Bodies of
+    /// several here-documents on one line follow each other in order.
+    pub fn here_doc(
+        &mut self,
+        delimiter: &str,
+        strip_tabs: bool,
+        quoted: bool,
+        span: Span,
+    ) -> Result<Word, ParseError> {
+        let unterminated = || {
+            ParseError::incomplete(
+                format!("here-document delimited by `{delimiter}` is not terminated"),
+                span,
+            )
+        };
+        let mut pos = match self.heredoc_end {
+            Some(end) => end,
+            None => self.src[self.pos..]
+                .find('\n')
+                .map(|i| self.pos + i + 1)
+                .ok_or_else(unterminated)?,
+        };
+        let mut body = String::new();
+        loop {
+            if pos >= self.src.len() {
+                return Err(unterminated());
+            }
+            let line_end = self.src[pos..]
+                .find('\n')
+                .map_or(self.src.len(), |i| pos + i);
+            let mut line = &self.src[pos..line_end];
+            if strip_tabs {
+                line = line.trim_start_matches('\t');
+            }
+            pos = line_end + 1;
+            if line == delimiter {
+                break;
+            }
+            body.push_str(line);
+            body.push('\n');
+        }
+        self.heredoc_end = Some(pos.min(self.src.len()));
+
+        let parts = if quoted {
+            vec![WordPart::Quoted(body)]
+        } else {
+            Lexer::new(&body)
+                .quoted_text(0, true)
+                .map_err(|err| ParseError { span, ..err })?
+        };
+        Ok(Word {
+            parts: vec![WordPart::DoubleQuoted(parts)],
+        })
+    }
+
+    /// Parses what follows a `$`, returning `None` when the `$` is literal. `quoted` is set
+    /// inside double quotes.
+    fn dollar(&mut self, start: usize, quoted: bool) -> Result<Option<WordPart>, ParseError> {
+        match self.peek() {
+            Some('{') => {
+                self.bump();
+                Ok(Some(WordPart::Param(self.braced_param(start, quoted)?)))
+            }
+            Some('(') if self.peek_nth(1) == Some('(') => {
+                self.pos += 2;
+                Ok(Some(WordPart::Arith(self.arith_text(start)?)))
+            }
+            Some('(') => {
+                self.bump();
+                let (list, end) = parse_substitution(self.src, self.pos, self.aliases)?;
+                self.pos = end;
+                Ok(Some(WordPart::Command(list)))
+            }
+            Some(c) if c == '_' || c.is_ascii_alphabetic() || is_special_param(c) => {
+                Ok(Some(WordPart::Param(ParamExp {
+                    name: self.param_name(false),
+                    op: ParamOp::None,
+                })))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Reads a variable name or a special parameter, or nothing if neither follows. Only
+    /// `braced` names of positional parameters can have more than one digit, as in `${10}`.
+    fn param_name(&mut self, braced: bool) -> String {
+        let rest = &self.src[self.pos..];
+        let len = match rest.chars().next() {
+            Some(c) if braced && c.is_ascii_digit() => rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len()),
+            Some(c) if is_special_param(c) => c.len_utf8(),
+            Some(c) if c == '_' || c.is_ascii_alphabetic() => rest
+                .find(|c: char| c != '_' && !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len()),
+            _ => 0,
+        };
+        self.pos += len;
+        rest[..len].to_owned()
+    }
+
+    /// Parses a `${...}` expansion after its `{`.
+    fn braced_param(&mut self, start: usize, quoted: bool) -> Result<ParamExp, ParseError> {
+        let length = self.peek() == Some('#') && !matches!(self.peek_nth(1), None | Some('}'));
+        if length {
+            self.bump();
+        }
+        let mut name = self.param_name(true);
+        if self.peek() == Some('[') && is_name(&name) {
+            let subscript = self.src[self.pos + 1..]
+                .split_once(']')
+                .map(|(subscript, _)| subscript.trim())
+                .filter(|subscript| {
+                    matches!(*subscript, "@" | "*")
+                        || subscript
+                            .strip_prefix('-')
+                            .unwrap_or(subscript)
+                            .parse::<usize>()
+                            .is_ok()
+                });
+            let Some(subscript) = subscript else {
+                return Err(self.bad_substitution(start));
+            };
+            name = format!("{name}[{subscript}]");
+            self.pos = self.src[self.pos..].find(']').unwrap() + self.pos + 1;
+        }
+        let op = match self.bump() {
+            None => return Err(self.unterminated_param(start)),
+            _ if name.is_empty() => return Err(self.bad_substitution(start)),
+            Some('}') if length => ParamOp::Length,
+            _ if length => return Err(self.bad_substitution(start)),
+            Some('}') => ParamOp::None,
+            Some(':') => match self.bump() {
+                None => return Err(self.unterminated_param(start)),
+                Some(c) => match test_kind(c) {
+                    Some(kind) => ParamOp::Test {
+                        kind,
+                        colon: true,
+                        word: self.param_word(start, quoted, false)?.0,
+                    },
+                    None => return Err(self.bad_substitution(start)),
+                },
+            },
+            Some(c @ ('#' | '%')) => {
+                let longest = self.peek() == Some(c);
+                if longest {
+                    self.bump();
+                }
+                let pattern = self.param_word(start, quoted, false)?.0;
+                if c == '#' {
+                    ParamOp::RemovePrefix { longest, pattern }
+                } else {
+                    ParamOp::RemoveSuffix { longest, pattern }
+                }
+            }
+            Some('/') => {
+                let mode = match self.peek() {
+                    Some('/') => ReplaceMode::All,
+                    Some('#') => ReplaceMode::Prefix,
+                    Some('%') => ReplaceMode::Suffix,
+                    _ => ReplaceMode::First,
+                };
+                if mode != ReplaceMode::First {
+                    self.bump();
+                }
+                let (pattern, end) = self.param_word(start, quoted, true)?;
+                let replacement = if end == '/' {
+                    self.param_word(start, quoted, false)?.0
+                } else {
+                    Word::default()
+                };
+                ParamOp::Replace {
+                    mode,
+                    pattern,
+                    replacement,
+                }
+            }
+            Some(c) => match test_kind(c) {
+                Some(kind) => ParamOp::Test {
+                    kind,
+                    colon: false,
+                    word: self.param_word(start, quoted, false)?.0,
+                },
+                None => return Err(self.bad_substitution(start)),
+            },
+        };
+        Ok(ParamExp { name, op })
+    }
+
+    /// Parses the word of a `${...}` operator up to the closing `}`, or up to a `/` when
+    /// `slash` is set, and returns it with the character that ended it. Inside double quotes
+    /// single quotes are literal and backslashes are kept as in a double-quoted string.
+    fn param_word(
+        &mut self,
+        start: usize,
+        quoted: bool,
+        slash: bool,
+    ) -> Result<(Word, char), ParseError> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let end = loop {
+            let pos = self.pos;
+            match self.bump() {
+                None => return Err(self.unterminated_param(start)),
+                Some('}') => break '}',
+                Some('/') if slash => break '/',
+                Some('\\') => match self.bump() {
+                    None => return Err(self.unterminated_param(start)),
+                    Some('\n') => {}
+                    Some(c) if quoted && !matches!(c, '$' | '`' | '"' | '\\' | '}') => {
+                        literal.push('\\');
+                        literal.push(c);
+                    }
+                    Some(c) => {
+                        flush(&mut literal, &mut parts);
+                        parts.push(WordPart::Quoted(c.to_string()));
+                    }
+                },
+                Some('\'') if !quoted => {
+                    flush(&mut literal, &mut parts);
+                    parts.push(self.single_quoted(pos)?);
+                }
+                Some('"') => {
+                    flush(&mut literal, &mut parts);
+                    parts.push(WordPart::DoubleQuoted(self.quoted_text(pos, false)?));
+                }
+                Some('`') => {
+                    flush(&mut literal, &mut parts);
+                    parts.push(self.backquoted(pos, quoted)?);
+                }
+                Some('$') => match self.dollar(pos, quoted)? {
+                    Some(part) => {
+                        flush(&mut literal, &mut parts);
+                        parts.push(part);
+                    }
+                    None => literal.push('$'),
+                },
+                Some(c) => literal.push(c),
+            }
+        };
+        flush(&mut literal, &mut parts);
+        Ok((Word { parts }, end))
+    }
+
+    /// Parses a `` `...` `` substitution after its opening backquote. Inside it a backslash
+    /// only quotes `$`, `` ` ``, `\\` and, within double quotes, `"`; the rest is parsed as
+    /// a command list of its own.
+    fn backquoted(&mut self, start: usize, quoted: bool) -> Result<WordPart, ParseError> {
+        let mut text = String::new();
+        loop {
+            match self.bump() {
+                None => {
+                    return Err(ParseError::incomplete(
+                        "unterminated backquote",
+                        Span::new(start, self.pos),
+                    ))
+                }
+                Some('`') => break,
+                Some('\\') => match self.peek() {
+                    Some(c @ ('$' | '`' | '\\')) => {
+                        self.bump();
+                        text.push(c);
+                    }
+                    Some('"') if quoted => {
+                        self.bump();
+                        text.push('"');
+                    }
+                    _ => text.push('\\'),
+                },
+                Some(c) => text.push(c),
+            }
+        }
+        let list = parse_with_aliases(&text, self.aliases)
+            .map_err(|err| ParseError::new(err.message, Span::new(start, self.pos)))?;
+        Ok(WordPart::Command(list))
+    }
+
+    /// Parses an arithmetic expression up to the `))` that closes the `((` at `start`.
+    /// Expansions in it are parsed as in double quotes.
+    pub fn arith_text(&mut self, start: usize) -> Result<Word, ParseError> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut depth = 0;
+        loop {
+            let pos = self.pos;
+            match self.bump() {
+                None => {
+                    return Err(ParseError::incomplete(
+                        "unterminated `((`",
+                        Span::new(start, self.pos),
+                    ))
+                }
+                Some('(') => {
+                    depth += 1;
+                    literal.push('(');
+                }
+                Some(')') if depth > 0 => {
+                    depth -= 1;
+                    literal.push(')');
+                }
+                Some(')') if self.peek() == Some(')') => {
+                    self.bump();
+                    break;
+                }
+                Some(')') => {
+                    return Err(ParseError::new("expected `))`", Span::new(start, self.pos)))
+                }
+                Some('\\') => match self.peek() {
+                    Some('\n') => {
+                        self.bump();
+                    }
+                    Some(c @ ('$' | '`' | '\\')) => {
+                        self.bump();
+                        literal.push(c);
+                    }
+                    _ => literal.push('\\'),
+                },
+                Some('"') => {
+                    flush(&mut literal, &mut parts);
+                    parts.push(WordPart::DoubleQuoted(self.quoted_text(pos, false)?));
+                }
+                Some('`') => {
+                    flush(&mut literal, &mut parts);
+                    parts.push(self.backquoted(pos, true)?);
+                }
+                Some('$') => match self.dollar(pos, true)? {
+                    Some(part) => {
+                        flush(&mut literal, &mut parts);
+                        parts.push(part);
+                    }
+                    None => literal.push('$'),
+                },
+                Some(c) => literal.push(c),
+            }
+        }
+        flush(&mut literal, &mut parts);
+        Ok(Word { parts })
+    }
+
+    fn unterminated_param(&self, start: usize) -> ParseError {
+        ParseError::incomplete("unterminated `${`", Span::new(start, self.src.len()))
+    }
+
+    fn bad_substitution(&self, start: usize) -> ParseError {
+        let end = self.src[self.pos..]
+            .find('}')
+            .map_or(self.src.len(), |i| self.pos + i + 1);
+        ParseError::new("bad substitution", Span::new(start, end))
+    }
+}
+
+fn test_kind(c: char) -> Option<TestKind> {
+    match c {
+        '-' => Some(TestKind::Default),
+        '=' => Some(TestKind::Assign),
+        '?' => Some(TestKind::Error),
+        '+' => Some(TestKind::Alternative),
+        _ => None,
+    }
+}
+
+fn is_special_param(c: char) -> bool {
+    matches!(c, '?' | '!' | '$' | '#' | '@' | '*') || c.is_ascii_digit()
+}
+
+fn flush(literal: &mut String, parts: &mut Vec<WordPart>) {
+    if !literal.is_empty() {
+        parts.push(WordPart::Literal(std::mem::take(literal)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn tokens(src: &str) -> Vec<TokenKind> {
+        let mut lexer = Lexer::new(src);
+        let mut tokens = Vec::new();
+        loop {
+            let token = lexer.next_token().unwrap();
+            if token.kind == TokenKind::Eof {
+                return tokens;
+            }
+            tokens.push(token.kind);
+        }
+    }
+
+    fn word(text: &str) -> TokenKind {
+        TokenKind::Word(Word {
+            parts: vec![WordPart::Literal(text.to_owned())],
+        })
+    }
+
+    #[test]
+    fn operators_take_the_longest_match() {
+        assert_eq!(
+            tokens("a&&b||c;;d&>>e<<<f"),
+            [
+                word("a"),
+                TokenKind::Op(Op::AndIf),
+                word("b"),
+                TokenKind::Op(Op::OrIf),
+                word("c"),
+                TokenKind::Op(Op::DSemi),
+                word("d"),
+                TokenKind::Op(Op::AndDGreat),
+                word("e"),
+                TokenKind::Op(Op::TLess),
+                word("f"),
+            ]
+        );
+        assert_eq!(
+            tokens("a >| b <> c"),
+            [
+                word("a"),
+                TokenKind::Op(Op::Clobber),
+                word("b"),
+                TokenKind::Op(Op::LessGreat),
+                word("c"),
+            ]
+        );
+    }
+
+    #[test]
+    fn digits_before_a_redirection_are_a_descriptor() {
+        assert_eq!(
+            tokens("2>x 10<y 3 >z"),
+            [
+                TokenKind::IoNumber(2),
+                TokenKind::Op(Op::Great),
+                word("x"),
+                TokenKind::IoNumber(10),
+                TokenKind::Op(Op::Less),
+                word("y"),
+                word("3"),
+                TokenKind::Op(Op::Great),
+                word("z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn spans_cover_the_token_text() {
+        let src = "echo  'a b' >>out";
+        let mut lexer = Lexer::new(src);
+        let mut texts = Vec::new();
+        loop {
+            let token = lexer.next_token().unwrap();
+            if token.kind == TokenKind::Eof {
+                break;
+            }
+            texts.push(&src[token.span.start..token.span.end]);
+        }
+        assert_eq!(texts, ["echo", "'a b'", ">>", "out"]);
+    }
+
+    #[test]
+    fn escaped_newlines_join_lines() {
+        assert_eq!(
+            tokens("ec\\\nho a \\\n b"),
+            [word("echo"), word("a"), word("b")]
+        );
+        assert_eq!(tokens("a\nb")[1], TokenKind::Newline);
+    }
+
+    #[test]
+    fn extglob_groups_stay_in_the_word() {
+        assert_eq!(
+            tokens("ls !(*.log|*.tmp)"),
+            [word("ls"), word("!(*.log|*.tmp)")]
+        );
+        assert_eq!(tokens("a (b)")[1], TokenKind::Op(Op::LParen));
+    }
+
+    #[test]
+    fn reads_substitutions_and_arithmetic() {
+        let [_, TokenKind::Word(substitution)] = &tokens("echo $(echo a | wc)")[..] else {
+            panic!("expected two words");
+        };
+        let [WordPart::Command(list)] = &substitution.parts[..] else {
+            panic!("not a command substitution: {substitution:?}");
+        };
+        assert_eq!(list.items[0].first.commands.len(), 2);
+
+        let [_, TokenKind::Word(backquoted)] = &tokens("echo `date`")[..] else {
+            panic!("expected two words");
+        };
+        assert!(matches!(&backquoted.parts[..], [WordPart::Command(_)]));
+
+        let [TokenKind::Word(arith)] = &tokens("$((1 + (2 * 3)))")[..] else {
+            panic!("expected one word");
+        };
+        let [WordPart::Arith(expr)] = &arith.parts[..] else {
+            panic!("not arithmetic: {arith:?}");
+        };
+        assert_eq!(expr.unquoted(), "1 + (2 * 3)");
+    }
+
+    #[test]
+    fn reads_array_subscripts() {
+        let [TokenKind::Word(word)] = &tokens("${PIPESTATUS[ -1 ]}")[..] else {
+            panic!("expected one word");
+        };
+        assert_eq!(
+            word.parts,
+            [WordPart::Param(ParamExp {
+                name: "PIPESTATUS[-1]".to_owned(),
+                op: ParamOp::None,
+            })]
+        );
+        assert!(Lexer::new("${x[y]}").next_token().is_err());
+        assert!(Lexer::new("${1[0]}").next_token().is_err());
+    }
+
+    #[test]
+    fn a_lone_dollar_is_literal() {
+        assert_eq!(tokens("a$ $"), [word("a$"), word("$")]);
+    }
+
+    #[test]
+    fn here_document_bodies_follow_the_line() {
+        let list = parse("cat <<A <<-B; echo x\none $v\nA\n\ttwo\n\tB\necho y").unwrap();
+        assert_eq!(list.items.len(), 3);
+        let crate::parser::ast::Command::Simple(cat) = &list.items[0].first.commands[0] else {
+            panic!("not a simple command");
+        };
+        assert_eq!(cat.redirects[0].target.unquoted(), "one $v\n");
+        assert_eq!(cat.redirects[1].target.unquoted(), "two\n");
+
+        let list = parse("cat <<'E'\n$v `x`\nE").unwrap();
+        let crate::parser::ast::Command::Simple(cat) = &list.items[0].first.commands[0] else {
+            panic!("not a simple command");
+        };
+        assert_eq!(
+            cat.redirects[0].target.parts,
+            [WordPart::DoubleQuoted(vec![WordPart::Quoted(
+                "$v `x`\n".to_owned()
+            )])]
+        );
+    }
+
+    #[test]
+    fn unterminated_text_is_incomplete_from_its_start() {
+        let mut lexer = Lexer::new("echo \"abc");
+        lexer.next_token().unwrap();
+        let err = lexer.next_token().unwrap_err();
+        assert!(err.incomplete);
+        assert_eq!(err.span, Span::new(5, 9));
+    }
+}
@@ -0,0 +1,1086 @@
+pub mod ast;
+mod lexer;
+
+use std::{collections::BTreeMap, fmt, mem, rc::Rc};
+
+use ast::{
+    AndOr, CaseItem, Command, CompoundCommand, Connector, FilePipe, Function, List, Pipeline,
+    Redirect, SimpleCommand, Word, WordPart,
+};
+use lexer::{Lexer, Op, Token, TokenKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+    /// The input ended early and could become valid with more lines.
+    pub incomplete: bool,
+}
+
+impl ParseError {
+    fn new(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            message: message.into(),
+            span,
+            incomplete: false,
+        }
+    }
+
+    fn incomplete(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            incomplete: true,
+            ..Self::new(message, span)
+        }
+    }
+
+    /// Formats the error with the offending line of `src` and a marker under the span.
+    pub fn render(&self, src: &str) -> String {
+        let line_start = src[..self.span.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = src[line_start..]
+            .find('\n')
+            .map_or(src.len(), |i| line_start + i);
+        let line = &src[line_start..line_end];
+        let column = src[line_start..self.span.start].chars().count();
+        let width = src[self.span.start..self.span.end.min(line_end)]
+            .chars()
+            .count()
+            .max(1);
+        format!(
+            "{}\n  {}\n  {}{}",
+            self,
+            line,
+            " ".repeat(column),
+            "^".repeat(width)
+        )
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "syntax error: {}", self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Alias names and the text each one stands for.
+pub type Aliases = BTreeMap<String, String>;
+
+static NO_ALIASES: Aliases = BTreeMap::new();
+
+#[cfg(test)]
+pub fn parse(src: &str) -> Result<List, ParseError> {
+    parse_with_aliases(src, &NO_ALIASES)
+}
+
+/// Parses `src`, replacing an unquoted command name that is one of `aliases` with the
+/// tokens of its text.
+pub fn parse_with_aliases(src: &str, aliases: &Aliases) -> Result<List, ParseError> {
+    let mut parser = Parser::new(src, 0, aliases);
+    let list = parser.list()?;
+    let token = parser.next()?;
+    if token.kind != TokenKind::Eof {
+        return Err(parser.unexpected(&token));
+    }
+    Ok(list)
+}
+
+/// Splits `src` into the text of its words and operators, as history word designators
+/// count them. Whatever follows a lexing error, such as an unterminated quote, is one word.
+pub fn split_words(src: &str) -> Vec<&str> {
+    let mut lexer = Lexer::new(src);
+    let mut words = Vec::new();
+    loop {
+        let start = lexer.pos();
+        match lexer.next_token() {
+            Ok(Token {
+                kind: TokenKind::Eof,
+                ..
+            }) => break,
+            Ok(Token {
+                kind: TokenKind::Newline,
+                ..
+            }) => {}
+            Ok(token) => words.push(&src[token.span.start..token.span.end]),
+            Err(_) => {
+                let rest = src[start..].trim();
+                if !rest.is_empty() {
+                    words.push(rest);
+                }
+                break;
+            }
+        }
+    }
+    words
+}
+
+/// Parses the commands of a `$(...)` substitution, starting at `start` just after the `(`.
+/// Returns them with the position after the closing `)`.
+fn parse_substitution(
+    src: &str,
+    start: usize,
+    aliases: &Aliases,
+) -> Result<(List, usize), ParseError> {
+    let mut parser = Parser::new(src, start, aliases);
+    let list = parser.list()?;
+    let token = parser.next()?;
+    match token.kind {
+        TokenKind::Op(Op::RParen) => Ok((list, token.span.end)),
+        TokenKind::Eof => Err(ParseError::incomplete(
+            "unterminated `$(`",
+            Span::new(start.saturating_sub(2), src.len()),
+        )),
+        _ => Err(parser.unexpected(&token)),
+    }
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    /// Reads `src`, or the text of the alias being expanded.
+    lexer: Lexer<'a>,
+    aliases: &'a Aliases,
+    /// The aliases being expanded, innermost last.
+    expansions: Vec<Expansion<'a>>,
+    /// The next token follows an alias whose text ends in a blank, so it is a command name
+    /// too.
+    after_blank_alias: bool,
+    peeked: Option<Token>,
+    /// End of the last token handed out by `next`.
+    last_end: usize,
+}
+
+/// An alias whose tokens are being read in place of its name.
+struct Expansion<'a> {
+    name: &'a str,
+    /// Where the name was in `src`, which stands for every token of the alias.
+    span: Span,
+    /// The lexer to go back to once the alias text is used up.
+    outer: Lexer<'a>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str, start: usize, aliases: &'a Aliases) -> Self {
+        Self {
+            src,
+            lexer: Lexer::at(src, start, aliases),
+            aliases,
+            expansions: Vec::new(),
+            after_blank_alias: false,
+            peeked: None,
+            last_end: start,
+        }
+    }
+
+    fn peek(&mut self) -> Result<&Token, ParseError> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.lex()?);
+        }
+        Ok(self.peeked.as_ref().unwrap())
+    }
+
+    fn next(&mut self) -> Result<Token, ParseError> {
+        let token = match self.peeked.take() {
+            Some(token) => token,
+            None => self.lex()?,
+        };
+        self.after_blank_alias = false;
+        self.last_end = token.span.end;
+        Ok(token)
+    }
+
+    /// Reads a token from the innermost alias that has any left, or from `src`.
+    fn lex(&mut self) -> Result<Token, ParseError> {
+        loop {
+            let token = self.lexer.next_token();
+            let Some(expansion) = self.expansions.last() else {
+                return token;
+            };
+            match token {
+                Ok(Token {
+                    kind: TokenKind::Eof,
+                    ..
+                }) => {
+                    let expansion = self.expansions.pop().unwrap();
+                    self.lexer = expansion.outer;
+                    self.after_blank_alias = self.aliases[expansion.name].ends_with([' ', '\t']);
+                }
+                Ok(token) => {
+                    return Ok(Token {
+                        span: expansion.span,
+                        ..token
+                    })
+                }
+                Err(err) => return Err(self.in_alias(err)),
+            }
+        }
+    }
+
+    /// Replaces the next token with the text of the alias it names, if it is an unquoted
+    /// alias name that is not already being expanded. Returns whether it did.
+    fn expand_alias(&mut self) -> Result<bool, ParseError> {
+        let aliases = self.aliases;
+        let token = self.peek()?;
+        let span = token.span;
+        let TokenKind::Word(word) = &token.kind else {
+            return Ok(false);
+        };
+        let [WordPart::Literal(name)] = &word.parts[..] else {
+            return Ok(false);
+        };
+        let Some((name, text)) = aliases.get_key_value(name) else {
+            return Ok(false);
+        };
+        if self
+            .expansions
+            .iter()
+            .any(|expansion| expansion.name == name)
+        {
+            return Ok(false);
+        }
+        let outer = mem::replace(&mut self.lexer, Lexer::at(text, 0, aliases));
+        self.expansions.push(Expansion { name, span, outer });
+        self.peeked = None;
+        self.after_blank_alias = false;
+        Ok(true)
+    }
+
+    /// Points an error in alias text at the alias name instead, as the text is not in `src`.
+    fn in_alias(&self, err: ParseError) -> ParseError {
+        match self.expansions.last() {
+            Some(expansion) => ParseError::new(err.message, expansion.span),
+            None => err,
+        }
+    }
+
+    /// The position in `src` after what the lexer has read.
+    fn lexer_end(&self) -> usize {
+        self.expansions
+            .last()
+            .map_or(self.lexer.pos(), |expansion| expansion.span.end)
+    }
+
+    fn unexpected(&self, token: &Token) -> ParseError {
+        let message = match &token.kind {
+            TokenKind::Op(op) => format!("unexpected token `{}`", op.as_str()),
+            TokenKind::Word(word) => format!("unexpected word `{}`", word.unquoted()),
+            TokenKind::IoNumber(fd) => format!("unexpected token `{fd}`"),
+            TokenKind::Newline => "unexpected newline".to_owned(),
+            TokenKind::Eof => return ParseError::incomplete("unexpected end of input", token.span),
+        };
+        ParseError::new(message, token.span)
+    }
+
+    fn skip_newlines(&mut self) -> Result<(), ParseError> {
+        while self.peek()?.kind == TokenKind::Newline {
+            self.next()?;
+        }
+        Ok(())
+    }
+
+    fn list(&mut self) -> Result<List, ParseError> {
+        let mut list = List::default();
+        loop {
+            self.skip_newlines()?;
+            let kind = &self.peek()?.kind;
+            if !starts_command(kind)
+                || reserved(kind).is_some_and(|word| TERMINATORS.contains(&word))
+            {
+                break;
+            }
+            let mut and_or = self.and_or()?;
+            match self.peek()?.kind {
+                TokenKind::Op(Op::Semi) | TokenKind::Newline => {
+                    self.next()?;
+                    list.items.push(and_or);
+                }
+                TokenKind::Op(Op::Amp) => {
+                    self.next()?;
+                    and_or.background = true;
+                    list.items.push(and_or);
+                }
+                _ => {
+                    list.items.push(and_or);
+                    break;
+                }
+            }
+        }
+        Ok(list)
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, ParseError> {
+        let mut and_or = AndOr {
+            first: self.pipeline()?,
+            rest: Vec::new(),
+            background: false,
+        };
+        loop {
+            let connector = match self.peek()?.kind {
+                TokenKind::Op(Op::AndIf) => Connector::And,
+                TokenKind::Op(Op::OrIf) => Connector::Or,
+                _ => break,
+            };
+            self.next()?;
+            self.skip_newlines()?;
+            and_or.rest.push((connector, self.pipeline()?));
+        }
+        Ok(and_or)
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let mut pipeline = Pipeline::default();
+        let start = self.peek()?.span.start;
+        pipeline.commands.push(self.command()?);
+        while self.peek()?.kind == TokenKind::Op(Op::Pipe) {
+            self.next()?;
+            self.skip_newlines()?;
+            pipeline.commands.push(self.command()?);
+        }
+        pipeline.text = self.src[start..self.last_end].to_owned();
+        Ok(pipeline)
+    }
+
+    fn command(&mut self) -> Result<Command, ParseError> {
+        if reserved(&self.peek()?.kind).is_none() && self.expand_alias()? {
+            while self.expand_alias()? {}
+            if !starts_command(&self.peek()?.kind) {
+                // The alias text was empty or only blanks.
+                return Ok(Command::Simple(SimpleCommand::default()));
+            }
+        }
+        let compound = match self.peek()?.kind {
+            TokenKind::Op(Op::DLParen) => {
+                let start = self.next()?.span.start;
+                let expr = self
+                    .lexer
+                    .arith_text(start)
+                    .map_err(|err| self.in_alias(err))?;
+                self.last_end = self.lexer_end();
+                CompoundCommand::Arith(expr)
+            }
+            ref kind => match reserved(kind) {
+                Some("{") => {
+                    self.next()?;
+                    let list = self.compound_list()?;
+                    self.expect_reserved("}")?;
+                    CompoundCommand::Group(list)
+                }
+                Some("if") => self.if_command()?,
+                Some(word @ ("while" | "until")) => {
+                    let until = word == "until";
+                    self.next()?;
+                    let condition = self.compound_list()?;
+                    let body = self.do_group()?;
+                    CompoundCommand::While {
+                        until,
+                        condition,
+                        body,
+                    }
+                }
+                Some("for") => self.for_command()?,
+                Some("case") => self.case_command()?,
+                Some("function") => {
+                    self.next()?;
+                    let token = self.next()?;
+                    let name = match &token.kind {
+                        TokenKind::Word(word) => function_name(word),
+                        _ => None,
+                    };
+                    let name = name.ok_or_else(|| self.unexpected(&token))?;
+                    if self.peek()?.kind == TokenKind::Op(Op::LParen) {
+                        self.next()?;
+                        self.expect_op(Op::RParen)?;
+                    }
+                    return self.function_body(name);
+                }
+                _ => {
+                    let command = self.simple_command()?;
+                    if self.peek()?.kind != TokenKind::Op(Op::LParen) {
+                        return Ok(Command::Simple(command));
+                    }
+                    let paren = self.next()?;
+                    let name = match &command.words[..] {
+                        [word]
+                            if command.assignments.is_empty() && command.redirects.is_empty() =>
+                        {
+                            function_name(word)
+                        }
+                        _ => None,
+                    };
+                    let name = name.ok_or_else(|| self.unexpected(&paren))?;
+                    self.expect_op(Op::RParen)?;
+                    return self.function_body(name);
+                }
+            },
+        };
+        let mut redirects = Vec::new();
+        while let Some(redirect) = self.next_redirect()? {
+            redirects.push(redirect);
+        }
+        Ok(Command::Compound(compound, redirects))
+    }
+
+    /// Parses the compound command of a function definition after its `()`.
+    fn function_body(&mut self, name: String) -> Result<Command, ParseError> {
+        self.skip_newlines()?;
+        let kind = &self.peek()?.kind;
+        let compound = *kind == TokenKind::Op(Op::DLParen)
+            || reserved(kind).is_some_and(|word| COMPOUND.contains(&word));
+        if !compound {
+            let token = self.next()?;
+            return Err(self.unexpected(&token));
+        }
+        Ok(Command::Function(Function {
+            name,
+            body: Rc::new(self.command()?),
+        }))
+    }
+
+    /// Consumes the operator `op`.
+    fn expect_op(&mut self, op: Op) -> Result<(), ParseError> {
+        let token = self.next()?;
+        match token.kind {
+            TokenKind::Op(next) if next == op => Ok(()),
+            _ => Err(self.unexpected(&token)),
+        }
+    }
+
+    /// Parses a list that must have at least one command, as in the parts of compound
+    /// commands.
+    fn compound_list(&mut self) -> Result<List, ParseError> {
+        let list = self.list()?;
+        if list.items.is_empty() {
+            let token = self.next()?;
+            return Err(self.unexpected(&token));
+        }
+        Ok(list)
+    }
+
+    /// Consumes the reserved word `word`.
+    fn expect_reserved(&mut self, word: &str) -> Result<(), ParseError> {
+        let token = self.next()?;
+        match token.kind {
+            ref kind if reserved(kind) == Some(word) => Ok(()),
+            TokenKind::Eof => Err(ParseError::incomplete(
+                format!("expected `{word}`"),
+                token.span,
+            )),
+            _ => Err(self.unexpected(&token)),
+        }
+    }
+
+    fn if_command(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.next()?;
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+        loop {
+            let condition = self.compound_list()?;
+            self.expect_reserved("then")?;
+            branches.push((condition, self.compound_list()?));
+            match reserved(&self.peek()?.kind) {
+                Some("elif") => {
+                    self.next()?;
+                }
+                Some("else") => {
+                    self.next()?;
+                    otherwise = Some(self.compound_list()?);
+                    break;
+                }
+                _ => break,
+            }
+        }
+        self.expect_reserved("fi")?;
+        Ok(CompoundCommand::If {
+            branches,
+            otherwise,
+        })
+    }
+
+    /// Parses `do list; done`.
+    fn do_group(&mut self) -> Result<List, ParseError> {
+        self.expect_reserved("do")?;
+        let body = self.compound_list()?;
+        self.expect_reserved("done")?;
+        Ok(body)
+    }
+
+    fn for_command(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.next()?;
+        if self.peek()?.kind == TokenKind::Op(Op::DLParen) {
+            let start = self.next()?.span.start;
+            let text = self
+                .lexer
+                .arith_text(start)
+                .map_err(|err| self.in_alias(err))?;
+            let span = Span::new(start, self.lexer_end());
+            let Ok([init, condition, step]) = <[Word; 3]>::try_from(split_arith(text)) else {
+                return Err(ParseError::new(
+                    "expected three expressions in `for ((...))`",
+                    span,
+                ));
+            };
+            if self.peek()?.kind == TokenKind::Op(Op::Semi) {
+                self.next()?;
+            }
+            self.skip_newlines()?;
+            return Ok(CompoundCommand::ArithFor {
+                init,
+                condition,
+                step,
+                body: self.do_group()?,
+            });
+        }
+
+        let token = self.next()?;
+        let name = match &token.kind {
+            TokenKind::Word(word) if ast::is_name(&word.unquoted()) => word.unquoted(),
+            _ => return Err(self.unexpected(&token)),
+        };
+        self.skip_newlines()?;
+        let mut words = None;
+        if reserved(&self.peek()?.kind) == Some("in") {
+            self.next()?;
+            let mut list = Vec::new();
+            while let TokenKind::Word(_) = self.peek()?.kind {
+                let TokenKind::Word(word) = self.next()?.kind else {
+                    unreachable!()
+                };
+                list.push(word);
+            }
+            words = Some(list);
+        }
+        match self.peek()?.kind {
+            TokenKind::Op(Op::Semi) | TokenKind::Newline => {
+                self.next()?;
+            }
+            _ if words.is_none() => {}
+            _ => {
+                let token = self.next()?;
+                return Err(self.unexpected(&token));
+            }
+        }
+        self.skip_newlines()?;
+        Ok(CompoundCommand::For {
+            name,
+            words,
+            body: self.do_group()?,
+        })
+    }
+
+    fn case_command(&mut self) -> Result<CompoundCommand, ParseError> {
+        self.next()?;
+        let token = self.next()?;
+        let TokenKind::Word(word) = token.kind else {
+            return Err(self.unexpected(&token));
+        };
+        self.skip_newlines()?;
+        self.expect_reserved("in")?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_newlines()?;
+            if reserved(&self.peek()?.kind) == Some("esac") {
+                self.next()?;
+                break;
+            }
+            if self.peek()?.kind == TokenKind::Op(Op::LParen) {
+                self.next()?;
+            }
+            let mut patterns = Vec::new();
+            loop {
+                let token = self.next()?;
+                let TokenKind::Word(pattern) = token.kind else {
+                    return Err(self.unexpected(&token));
+                };
+                patterns.push(pattern);
+                let token = self.next()?;
+                match token.kind {
+                    TokenKind::Op(Op::Pipe) => {}
+                    TokenKind::Op(Op::RParen) => break,
+                    _ => return Err(self.unexpected(&token)),
+                }
+            }
+            let body = self.list()?;
+            items.push(CaseItem { patterns, body });
+            match self.peek()?.kind {
+                TokenKind::Op(Op::DSemi) => {
+                    self.next()?;
+                }
+                ref kind if reserved(kind) == Some("esac") => {}
+                _ => {
+                    let token = self.next()?;
+                    return Err(self.unexpected(&token));
+                }
+            }
+        }
+        Ok(CompoundCommand::Case { word, items })
+    }
+
+    fn simple_command(&mut self) -> Result<SimpleCommand, ParseError> {
+        let mut command = SimpleCommand::default();
+        loop {
+            if let Some(redirect) = self.next_redirect()? {
+                command.redirects.push(redirect);
+                continue;
+            }
+            if command.words.is_empty() || self.after_blank_alias {
+                while self.expand_alias()? {}
+            }
+            let TokenKind::Word(_) = self.peek()?.kind else {
+                break;
+            };
+            let TokenKind::Word(word) = self.next()?.kind else {
+                unreachable!()
+            };
+            match word.as_assignment() {
+                Some(assignment) if command.words.is_empty() => {
+                    command.assignments.push(assignment)
+                }
+                _ => command.words.push(word),
+            }
+        }
+        if command.words.is_empty()
+            && command.redirects.is_empty()
+            && command.assignments.is_empty()
+        {
+            let token = self.next()?;
+            return Err(self.unexpected(&token));
+        }
+        Ok(command)
+    }
+
+    /// Parses a redirection if one comes next.
+    fn next_redirect(&mut self) -> Result<Option<Redirect>, ParseError> {
+        match self.peek()?.kind {
+            TokenKind::IoNumber(fd) => {
+                self.next()?;
+                let token = self.next()?;
+                let TokenKind::Op(op) = token.kind else {
+                    return Err(self.unexpected(&token));
+                };
+                Ok(Some(self.redirect(Some(fd), op, token.span)?))
+            }
+            TokenKind::Op(op) if redirect_kind(op).is_some() => {
+                let span = self.next()?.span;
+                Ok(Some(self.redirect(None, op, span)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn redirect(&mut self, fd: Option<i32>, op: Op, span: Span) -> Result<Redirect, ParseError> {
+        let (default_fd, mut kind) = redirect_kind(op)
+            .ok_or_else(|| ParseError::new(format!("unexpected token `{}`", op.as_str()), span))?;
+        let token = self.next()?;
+        let TokenKind::Word(mut target) = token.kind else {
+            return Err(match token.kind {
+                TokenKind::Eof => ParseError::new("expected a word after redirection", span),
+                _ => self.unexpected(&token),
+            });
+        };
+        if matches!(op, Op::DLess | Op::DLessDash) {
+            let quoted = target
+                .parts
+                .iter()
+                .any(|part| !matches!(part, WordPart::Literal(_)));
+            target = self
+                .lexer
+                .here_doc(&target.unquoted(), op == Op::DLessDash, quoted, span)
+                .map_err(|err| self.in_alias(err))?;
+        }
+        if kind == FilePipe::Dup {
+            let text = target.unquoted();
+            if text != "-" && text.parse::<i32>().is_err() {
+                if op == Op::LessAnd || fd.is_some() {
+                    return Err(ParseError::new(
+                        format!("`{text}`: file descriptor expected"),
+                        token.span,
+                    ));
+                }
+                kind = FilePipe::WriteAll;
+            }
+        }
+        Ok(Redirect {
+            fd: fd.unwrap_or(default_fd),
+            kind,
+            target,
+        })
+    }
+}
+
+/// Words that start or continue compound commands when they appear where a command may
+/// start.
+const RESERVED: [&str; 16] = [
+    "if", "then", "elif", "else", "fi", "while", "until", "for", "in", "do", "done", "case",
+    "esac", "{", "}", "function",
+];
+
+/// Reserved words that end the list before them.
+const TERMINATORS: [&str; 8] = ["then", "elif", "else", "fi", "do", "done", "esac", "}"];
+
+/// Reserved words starting the compound commands that can be function bodies.
+const COMPOUND: [&str; 6] = ["{", "if", "while", "until", "for", "case"];
+
+/// The reserved word `kind` is, if it is an unquoted one.
+fn reserved(kind: &TokenKind) -> Option<&'static str> {
+    let TokenKind::Word(word) = kind else {
+        return None;
+    };
+    match &word.parts[..] {
+        [WordPart::Literal(text)] => RESERVED.iter().copied().find(|word| word == text),
+        _ => None,
+    }
+}
+
+/// The name a function definition gives with `word`, which must be unquoted and not a
+/// reserved word.
+fn function_name(word: &Word) -> Option<String> {
+    match &word.parts[..] {
+        [WordPart::Literal(name)] if !RESERVED.contains(&name.as_str()) => Some(name.clone()),
+        _ => None,
+    }
+}
+
+/// Splits the text of `for ((init; condition; step))` at its semicolons.
+fn split_arith(text: Word) -> Vec<Word> {
+    let mut words = vec![Word::default()];
+    for part in text.parts {
+        let WordPart::Literal(literal) = part else {
+            words.last_mut().unwrap().parts.push(part);
+            continue;
+        };
+        for (i, piece) in literal.split(';').enumerate() {
+            if i > 0 {
+                words.push(Word::default());
+            }
+            if !piece.is_empty() {
+                let word = words.last_mut().unwrap();
+                word.parts.push(WordPart::Literal(piece.to_owned()));
+            }
+        }
+    }
+    words
+}
+
+fn starts_command(kind: &TokenKind) -> bool {
+    match kind {
+        TokenKind::Word(_) | TokenKind::IoNumber(_) | TokenKind::Op(Op::DLParen) => true,
+        TokenKind::Op(op) => redirect_kind(*op).is_some(),
+        _ => false,
+    }
+}
+
+/// The default descriptor and kind of a redirection operator.
+fn redirect_kind(op: Op) -> Option<(i32, FilePipe)> {
+    Some(match op {
+        Op::Less => (0, FilePipe::Read),
+        Op::DLess | Op::DLessDash => (0, FilePipe::HereDoc),
+        Op::TLess => (0, FilePipe::HereString),
+        Op::Great | Op::Clobber => (1, FilePipe::Write),
+        Op::DGreat => (1, FilePipe::Append),
+        Op::LessGreat => (0, FilePipe::ReadWrite),
+        Op::AndGreat => (1, FilePipe::WriteAll),
+        Op::AndDGreat => (1, FilePipe::AppendAll),
+        Op::LessAnd => (0, FilePipe::Dup),
+        Op::GreatAnd => (1, FilePipe::Dup),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn literal(text: &str) -> WordPart {
+        WordPart::Literal(text.to_owned())
+    }
+
+    /// The only simple command of `src`.
+    fn simple(src: &str) -> SimpleCommand {
+        let list = parse(src).unwrap();
+        match &list.items[..] {
+            [and_or] => match &and_or.first.commands[..] {
+                [Command::Simple(command)] => command.clone(),
+                commands => panic!("not one simple command: {commands:?}"),
+            },
+            items => panic!("not one command: {items:?}"),
+        }
+    }
+
+    fn words(src: &str) -> Vec<String> {
+        command_words(&simple(src))
+    }
+
+    fn command_words(command: &SimpleCommand) -> Vec<String> {
+        command.words.iter().map(Word::unquoted).collect()
+    }
+
+    #[test]
+    fn splits_words_on_blanks() {
+        assert_eq!(words("  echo   a\tb  "), ["echo", "a", "b"]);
+        assert_eq!(words("echo a # comment"), ["echo", "a"]);
+        assert_eq!(words("echo a#b"), ["echo", "a#b"]);
+    }
+
+    #[test]
+    fn keeps_quoting_in_word_parts() {
+        let command = simple(r#"echo 'a b'"c $x"d\ e"#);
+        assert_eq!(
+            command.words[1].parts,
+            [
+                WordPart::Quoted("a b".to_owned()),
+                WordPart::DoubleQuoted(vec![
+                    literal("c "),
+                    WordPart::Param(ast::ParamExp {
+                        name: "x".to_owned(),
+                        op: ast::ParamOp::None,
+                    }),
+                ]),
+                literal("d"),
+                WordPart::Quoted(" ".to_owned()),
+                literal("e"),
+            ]
+        );
+        assert_eq!(words(r#"echo "a | b" 'c;d'"#), ["echo", "a | b", "c;d"]);
+    }
+
+    #[test]
+    fn and_or_binds_tighter_than_separators() {
+        let list = parse("a; b && c || d & e").unwrap();
+        assert_eq!(list.items.len(), 3);
+        let second = &list.items[1];
+        assert!(second.background);
+        assert_eq!(
+            second
+                .rest
+                .iter()
+                .map(|(connector, _)| *connector)
+                .collect::<Vec<_>>(),
+            [Connector::And, Connector::Or]
+        );
+        assert!(!list.items[2].background);
+        assert_eq!(list.text(), "a; b && c || d & e");
+    }
+
+    #[test]
+    fn pipes_bind_tighter_than_and_or() {
+        let list = parse("a | b && c | d | e").unwrap();
+        let and_or = &list.items[0];
+        assert_eq!(and_or.first.commands.len(), 2);
+        assert_eq!(and_or.rest[0].1.commands.len(), 3);
+    }
+
+    #[test]
+    fn newlines_separate_commands_and_continue_after_operators() {
+        assert_eq!(parse("a\nb\n\nc").unwrap().items.len(), 3);
+        let list = parse("a &&\n b |\n c").unwrap();
+        assert_eq!(list.items.len(), 1);
+        assert_eq!(list.items[0].rest[0].1.commands.len(), 2);
+    }
+
+    #[test]
+    fn parses_redirections() {
+        let command = simple("cmd <in >out 2>&1 3>>log &>all 4<>rw");
+        let redirects: Vec<(i32, FilePipe, String)> = command
+            .redirects
+            .iter()
+            .map(|redirect| (redirect.fd, redirect.kind, redirect.target.unquoted()))
+            .collect();
+        assert_eq!(
+            redirects,
+            [
+                (0, FilePipe::Read, "in".to_owned()),
+                (1, FilePipe::Write, "out".to_owned()),
+                (2, FilePipe::Dup, "1".to_owned()),
+                (3, FilePipe::Append, "log".to_owned()),
+                (1, FilePipe::WriteAll, "all".to_owned()),
+                (4, FilePipe::ReadWrite, "rw".to_owned()),
+            ]
+        );
+        assert_eq!(command.words.len(), 1);
+    }
+
+    #[test]
+    fn a_number_is_a_descriptor_only_right_before_the_operator() {
+        let command = simple("echo 2 >x");
+        assert_eq!(command.words.len(), 2);
+        assert_eq!(command.redirects[0].fd, 1);
+        assert_eq!(simple("echo a2>x").redirects[0].fd, 1);
+    }
+
+    #[test]
+    fn separates_assignments_from_words() {
+        let command = simple("A=1 B='x y' cmd C=2");
+        let names: Vec<&str> = command
+            .assignments
+            .iter()
+            .map(|assignment| assignment.name.as_str())
+            .collect();
+        assert_eq!(names, ["A", "B"]);
+        assert_eq!(command.assignments[1].value.unquoted(), "x y");
+        assert_eq!(
+            command.words.iter().map(Word::unquoted).collect::<Vec<_>>(),
+            ["cmd", "C=2"]
+        );
+    }
+
+    #[test]
+    fn reserved_words_only_start_commands() {
+        assert_eq!(words("echo if then fi"), ["echo", "if", "then", "fi"]);
+        assert_eq!(words("'if'"), ["if"]);
+        let list = parse("if a; then b; elif c; then d; else e; fi").unwrap();
+        let Command::Compound(
+            CompoundCommand::If {
+                branches,
+                otherwise,
+            },
+            _,
+        ) = &list.items[0].first.commands[0]
+        else {
+            panic!("not an if");
+        };
+        assert_eq!(branches.len(), 2);
+        assert!(otherwise.is_some());
+    }
+
+    #[test]
+    fn parses_loops_case_and_functions() {
+        let list = parse("for x in a b; do echo $x; done").unwrap();
+        let Command::Compound(CompoundCommand::For { name, words, .. }, _) =
+            &list.items[0].first.commands[0]
+        else {
+            panic!("not a for loop");
+        };
+        assert_eq!(name, "x");
+        assert_eq!(words.as_ref().unwrap().len(), 2);
+
+        let list = parse("case $x in a|b) one;; *) two;; esac").unwrap();
+        let Command::Compound(CompoundCommand::Case { items, .. }, _) =
+            &list.items[0].first.commands[0]
+        else {
+            panic!("not a case");
+        };
+        assert_eq!(items[0].patterns.len(), 2);
+        assert_eq!(items.len(), 2);
+
+        let list = parse("f() { echo hi; } >out").unwrap();
+        let Command::Function(function) = &list.items[0].first.commands[0] else {
+            panic!("not a function");
+        };
+        assert_eq!(function.name, "f");
+        assert!(matches!(&*function.body, Command::Compound(_, redirects) if redirects.len() == 1));
+    }
+
+    #[test]
+    fn unfinished_input_is_incomplete() {
+        for src in [
+            "echo 'abc",
+            "echo \"abc",
+            "echo abc\\",
+            "a &&",
+            "a |",
+            "if true; then",
+            "while true; do echo",
+            "f() {",
+            "echo $(ls",
+            "cat <<EOF\nbody",
+        ] {
+            let err = parse(src).unwrap_err();
+            assert!(err.incomplete, "{src:?}: {err}");
+        }
+    }
+
+    #[test]
+    fn syntax_errors_point_at_the_token() {
+        let src = "echo a | | b";
+        let err = parse(src).unwrap_err();
+        assert!(!err.incomplete);
+        assert_eq!(&src[err.span.start..err.span.end], "|");
+        assert_eq!(err.span.start, 9);
+        assert_eq!(
+            err.render(src),
+            format!("{err}\n  echo a | | b\n           ^")
+        );
+
+        for src in [")", "a; ; b", "fi", "echo >", "do echo", "a && || b"] {
+            let err = parse(src).unwrap_err();
+            assert!(!err.incomplete, "{src:?} should be an error");
+        }
+    }
+
+    #[test]
+    fn error_render_shows_the_line_of_the_error() {
+        let src = "echo ok\necho )";
+        let err = parse(src).unwrap_err();
+        assert!(err.render(src).ends_with("\n  echo )\n       ^"));
+    }
+
+    #[test]
+    fn aliases_are_replaced_by_their_tokens() {
+        let aliases = Aliases::from(
+            [
+                ("ll", "ls -l | wc -l"),
+                ("ls", "ls -a"),
+                ("a", "b x"),
+                ("b", "a y"),
+                ("sudo", "env "),
+                ("c", "if a; then b; fi"),
+                ("e", ""),
+                ("bad", "echo )"),
+            ]
+            .map(|(name, text)| (name.to_owned(), text.to_owned())),
+        );
+        let pipeline = |src| {
+            parse_with_aliases(src, &aliases).unwrap().items[0]
+                .first
+                .clone()
+        };
+        let words = |src| match &pipeline(src).commands[..] {
+            [Command::Simple(command)] => command_words(command),
+            commands => panic!("not one simple command: {commands:?}"),
+        };
+
+        let ll = pipeline("ll /tmp && true");
+        assert_eq!(ll.text, "ll /tmp");
+        let [Command::Simple(ls), Command::Simple(wc)] = &ll.commands[..] else {
+            panic!("not a pipeline of two: {ll:?}");
+        };
+        assert_eq!(command_words(ls), ["ls", "-a", "-l"]);
+        assert_eq!(command_words(wc), ["wc", "-l", "/tmp"]);
+
+        assert_eq!(words("a"), ["a", "y", "x"]);
+        assert_eq!(words("sudo ls ls"), ["env", "ls", "-a", "ls"]);
+        assert_eq!(words("X=1 ls"), ["ls", "-a"]);
+        assert_eq!(words("echo ls"), ["echo", "ls"]);
+        assert_eq!(words("\\ls"), ["ls"]);
+        assert_eq!(words("e echo"), ["echo"]);
+        assert!(matches!(
+            pipeline("c").commands[..],
+            [Command::Compound(CompoundCommand::If { .. }, _)]
+        ));
+
+        let src = "true; bad";
+        let err = parse_with_aliases(src, &aliases).unwrap_err();
+        assert_eq!(&src[err.span.start..err.span.end], "bad");
+    }
+
+    #[test]
+    fn split_words_keeps_source_text() {
+        assert_eq!(
+            split_words("echo 'a b' \"c\"|wc -l >out"),
+            ["echo", "'a b'", "\"c\"", "|", "wc", "-l", ">", "out"]
+        );
+        assert_eq!(split_words("echo 'open"), ["echo", "'open"]);
+        assert!(split_words("  ").is_empty());
+    }
+}
@@ -0,0 +1,351 @@
+/// A shell pattern such as `*.rs` or `[a-z]?`. A backslash makes the next character literal.
+/// With extended globbing `?(...)`, `*(...)`, `+(...)`, `@(...)` and `!(...)` match
+/// `|`-separated patterns zero or one, any, at least one, exactly one time, or anything but
+/// them.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    tokens: Vec<Token>,
+}
+
+#[derive(Debug, Clone)]
+enum Token {
+    Char(char),
+    Any,
+    Star,
+    Class {
+        negated: bool,
+        items: Vec<ClassItem>,
+    },
+    Group {
+        kind: char,
+        alternatives: Vec<Vec<Token>>,
+    },
+}
+
+#[derive(Debug, Clone)]
+enum ClassItem {
+    Char(char),
+    Range(char, char),
+    Named(String),
+}
+
+impl Pattern {
+    pub fn new(pattern: &str, extglob: bool) -> Self {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut i = 0;
+        Self {
+            tokens: parse_tokens(&chars, &mut i, extglob, false),
+        }
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        match_tokens(&self.tokens, &chars)
+    }
+}
+
+/// Whether `pattern` has unescaped wildcards, so it can match more than one string.
+pub fn has_wildcards(pattern: &str, extglob: bool) -> bool {
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            '!' | '@' | '+' if extglob && chars.peek() == Some(&'(') => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Removes the backslashes of a pattern without wildcards.
+pub fn unescape(pattern: &str) -> String {
+    let mut out = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Escapes `text` so it matches only itself when used in a pattern.
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(
+            c,
+            '\\' | '*' | '?' | '[' | ']' | '!' | '@' | '+' | '(' | ')' | '|'
+        ) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Parses tokens from `chars[*i..]`. Inside an extended glob group parsing stops at the `|`
+/// or `)` ending the current alternative.
+fn parse_tokens(chars: &[char], i: &mut usize, extglob: bool, nested: bool) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    while *i < chars.len() {
+        let c = chars[*i];
+        if nested && matches!(c, '|' | ')') {
+            break;
+        }
+        if extglob && matches!(c, '?' | '*' | '+' | '@' | '!') && chars.get(*i + 1) == Some(&'(') {
+            if let Some((token, end)) = parse_group(chars, *i) {
+                tokens.push(token);
+                *i = end;
+                continue;
+            }
+        }
+        match c {
+            '\\' if *i + 1 < chars.len() => {
+                tokens.push(Token::Char(chars[*i + 1]));
+                *i += 1;
+            }
+            '?' => tokens.push(Token::Any),
+            '*' => {
+                if !matches!(tokens.last(), Some(Token::Star)) {
+                    tokens.push(Token::Star);
+                }
+            }
+            '[' => match parse_class(&chars[*i + 1..]) {
+                Some((token, len)) => {
+                    tokens.push(token);
+                    *i += len;
+                }
+                None => tokens.push(Token::Char('[')),
+            },
+            c => tokens.push(Token::Char(c)),
+        }
+        *i += 1;
+    }
+    tokens
+}
+
+/// Parses an extended glob group starting at `chars[start]`, returning it and the index
+/// after its `)`.
+fn parse_group(chars: &[char], start: usize) -> Option<(Token, usize)> {
+    let mut i = start + 2;
+    let mut alternatives = Vec::new();
+    loop {
+        alternatives.push(parse_tokens(chars, &mut i, true, true));
+        match chars.get(i)? {
+            '|' => i += 1,
+            _ => {
+                let kind = chars[start];
+                return Some((Token::Group { kind, alternatives }, i + 1));
+            }
+        }
+    }
+}
+
+/// Parses a bracket expression after its `[`, returning it and the number of characters used
+/// including the closing `]`.
+fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+    let mut i = 0;
+    let negated = matches!(chars.first(), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let mut items = Vec::new();
+    let start = i;
+    loop {
+        let c = *chars.get(i)?;
+        if c == ']' && i > start {
+            return Some((Token::Class { negated, items }, i + 1));
+        }
+        if c == '[' && chars.get(i + 1) == Some(&':') {
+            let rest: String = chars[i + 2..].iter().collect();
+            if let Some(end) = rest.find(":]") {
+                items.push(ClassItem::Named(rest[..end].to_owned()));
+                i += 2 + rest[..end].chars().count() + 2;
+                continue;
+            }
+        }
+        let c = if c == '\\' && i + 1 < chars.len() {
+            i += 1;
+            chars[i]
+        } else {
+            c
+        };
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|c| *c != ']') {
+            items.push(ClassItem::Range(c, chars[i + 2]));
+            i += 3;
+        } else {
+            items.push(ClassItem::Char(c));
+            i += 1;
+        }
+    }
+}
+
+fn class_matches(items: &[ClassItem], c: char) -> bool {
+    items.iter().any(|item| match item {
+        ClassItem::Char(item) => *item == c,
+        ClassItem::Range(from, to) => (*from..=*to).contains(&c),
+        ClassItem::Named(name) => match name.as_str() {
+            "alpha" => c.is_alphabetic(),
+            "digit" => c.is_ascii_digit(),
+            "alnum" => c.is_alphanumeric(),
+            "upper" => c.is_uppercase(),
+            "lower" => c.is_lowercase(),
+            "space" => c.is_whitespace(),
+            "blank" => c == ' ' || c == '\t',
+            "punct" => c.is_ascii_punctuation(),
+            "xdigit" => c.is_ascii_hexdigit(),
+            "cntrl" => c.is_control(),
+            "print" => !c.is_control(),
+            "graph" => !c.is_control() && !c.is_whitespace(),
+            _ => false,
+        },
+    })
+}
+
+fn match_tokens(tokens: &[Token], text: &[char]) -> bool {
+    match tokens.split_first() {
+        None => text.is_empty(),
+        Some((Token::Star, rest)) => (0..=text.len()).any(|skip| match_tokens(rest, &text[skip..])),
+        Some((Token::Group { kind, alternatives }, rest)) => {
+            let once = |text: &[char]| {
+                (0..=text.len()).any(|k| {
+                    any_matches(alternatives, &text[..k]) && match_tokens(rest, &text[k..])
+                })
+            };
+            match kind {
+                '@' => once(text),
+                '?' => match_tokens(rest, text) || once(text),
+                '!' => (0..=text.len()).any(|k| {
+                    !any_matches(alternatives, &text[..k]) && match_tokens(rest, &text[k..])
+                }),
+                _ => match_repeat(alternatives, rest, text, *kind == '+'),
+            }
+        }
+        Some((token, rest)) => {
+            let Some((c, text)) = text.split_first() else {
+                return false;
+            };
+            let matched = match token {
+                Token::Char(expected) => expected == c,
+                Token::Any => true,
+                Token::Class { negated, items } => class_matches(items, *c) != *negated,
+                Token::Star | Token::Group { .. } => unreachable!(),
+            };
+            matched && match_tokens(rest, text)
+        }
+    }
+}
+
+fn any_matches(alternatives: &[Vec<Token>], text: &[char]) -> bool {
+    alternatives
+        .iter()
+        .any(|alternative| match_tokens(alternative, text))
+}
+
+/// Matches `*(...)` or, when `at_least_one` is set, `+(...)` followed by `rest`.
+fn match_repeat(
+    alternatives: &[Vec<Token>],
+    rest: &[Token],
+    text: &[char],
+    at_least_one: bool,
+) -> bool {
+    (!at_least_one && match_tokens(rest, text))
+        || (1..=text.len()).any(|k| {
+            any_matches(alternatives, &text[..k])
+                && match_repeat(alternatives, rest, &text[k..], false)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Pattern::new(pattern, true).matches(text)
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("*.rs", ".rs"));
+        assert!(!matches("*.rs", "main.rsx"));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", "ac"));
+        assert!(matches("**", ""));
+        assert!(matches("*a*b*", "xxaybz"));
+        assert!(matches("é?", "éà"));
+    }
+
+    #[test]
+    fn matches_bracket_expressions() {
+        assert!(matches("[abc]", "b"));
+        assert!(matches("[a-c][!a-c][^a-c]", "bxy"));
+        assert!(!matches("[!a-c]", "a"));
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[a-]", "-"));
+        assert!(matches("[[:digit:][:upper:]]", "Q"));
+        assert!(!matches("[[:digit:]]", "x"));
+        assert!(matches("[\\]]", "]"));
+        // An unclosed bracket is an ordinary character.
+        assert!(matches("[ab", "[ab"));
+    }
+
+    #[test]
+    fn backslash_quotes_the_next_character() {
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(matches("a\\?", "a?"));
+        assert!(matches("trailing\\", "trailing\\"));
+    }
+
+    #[test]
+    fn matches_extended_groups() {
+        assert!(matches("@(a|bc).rs", "bc.rs"));
+        assert!(!matches("@(a|bc).rs", "abc.rs"));
+        assert!(matches("?(x)y", "y"));
+        assert!(matches("?(x)y", "xy"));
+        assert!(!matches("?(x)y", "xxy"));
+        assert!(matches("*(ab)", ""));
+        assert!(matches("*(ab)", "ababab"));
+        assert!(!matches("+(ab)", ""));
+        assert!(matches("+(a|b)c", "abbac"));
+        assert!(matches("!(*.log)", "main.rs"));
+        assert!(!matches("!(*.log)", "debug.log"));
+        assert!(matches("@(a|+(b))", "bbb"));
+    }
+
+    #[test]
+    fn extended_groups_need_extglob() {
+        assert!(!Pattern::new("@(a)", false).matches("a"));
+        assert!(Pattern::new("@(a)", false).matches("@(a)"));
+        assert!(Pattern::new("!(a)", false).matches("!(a)"));
+        // An unclosed group is literal.
+        assert!(matches("@(a", "@(a"));
+    }
+
+    #[test]
+    fn finds_wildcards() {
+        assert!(has_wildcards("a*", false));
+        assert!(has_wildcards("[ab]", false));
+        assert!(!has_wildcards("a\\*", false));
+        assert!(!has_wildcards("@(a)", false));
+        assert!(has_wildcards("@(a)", true));
+        assert!(!has_wildcards("\\@(a)", true));
+    }
+
+    #[test]
+    fn escaped_text_matches_only_itself() {
+        for text in ["*.rs", "[a]", "@(a).rs", "!(x)", "+(y|z)", "a\\b", "?"] {
+            let escaped = escape(text);
+            assert!(!has_wildcards(&escaped, true), "{escaped}");
+            assert!(matches(&escaped, text), "{escaped}");
+            assert_eq!(unescape(&escaped), text);
+        }
+        assert!(!matches(&escape("@(a)"), "a"));
+    }
+}
@@ -0,0 +1,264 @@
+use anyhow::{anyhow, Result};
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, Seek, Write},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::fs::OpenOptionsExt,
+    },
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    parser::ast::{FilePipe, Redirect},
+    shell::Shell,
+};
+
+#[derive(Debug)]
+pub enum Source {
+    File(OwnedFd),
+    Dup(RawFd),
+    Close,
+}
+
+/// Redirections of one command, resolved to descriptors in the order they were written.
+#[derive(Debug, Default)]
+pub struct Redirections(Vec<(RawFd, Source)>);
+
+impl Shell {
+    pub fn open_redirects(&mut self, redirects: &[Redirect]) -> Result<Redirections> {
+        let mut plan = Vec::new();
+        for redirect in redirects {
+            let target = self.tilde(&redirect.target, false);
+            let target = self.expand_word(&target)?;
+            let mut options = File::options();
+            let fd = redirect.fd;
+            match redirect.kind {
+                FilePipe::Read => options.read(true),
+                FilePipe::Write | FilePipe::WriteAll => {
+                    options.write(true).create(true).truncate(true)
+                }
+                FilePipe::Append | FilePipe::AppendAll => options.append(true).create(true),
+                FilePipe::ReadWrite => options.read(true).write(true).create(true),
+                FilePipe::HereDoc | FilePipe::HereString => {
+                    let mut text = target;
+                    if redirect.kind == FilePipe::HereString {
+                        text.push('\n');
+                    }
+                    plan.push((fd, Source::File(here_doc_file(&text)?.into())));
+                    continue;
+                }
+                FilePipe::Dup => {
+                    let source = if target == "-" {
+                        Source::Close
+                    } else {
+                        Source::Dup(
+                            target
+                                .parse()
+                                .map_err(|_| anyhow!("{target}: ambiguous redirect"))?,
+                        )
+                    };
+                    plan.push((fd, source));
+                    continue;
+                }
+            };
+            let file = options
+                .open(&target)
+                .map_err(|e| anyhow!("{target}: {e}"))?;
+            plan.push((fd, Source::File(file.into())));
+            if matches!(redirect.kind, FilePipe::WriteAll | FilePipe::AppendAll) {
+                plan.push((2, Source::Dup(fd)));
+            }
+        }
+        let mut redirections = Redirections(plan);
+        redirections.lift_files()?;
+        Ok(redirections)
+    }
+}
+
+impl Redirections {
+    /// One more than the highest descriptor the redirections name, and at least 10.
+    /// Descriptors from there up are not replaced by any of them.
+    fn floor(&self) -> RawFd {
+        self.0
+            .iter()
+            .map(|(fd, source)| match source {
+                Source::Dup(source) => (*fd).max(*source),
+                _ => *fd,
+            })
+            .max()
+            .map_or(10, |fd| (fd + 1).max(10))
+    }
+
+    /// Moves the opened files to the floor or above, so that installing one redirection
+    /// cannot close a file a later one still needs.
+    fn lift_files(&mut self) -> io::Result<()> {
+        let floor = self.floor();
+        for (_, source) in &mut self.0 {
+            if let Source::File(file) = source {
+                if file.as_raw_fd() < floor {
+                    let fd = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_DUPFD_CLOEXEC, floor) };
+                    if fd == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    *file = unsafe { OwnedFd::from_raw_fd(fd) };
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Installs the redirections on the current process. Only async-signal-safe calls are
+    /// made, so this can run between `fork` and `exec`.
+    pub fn apply(&self) -> io::Result<()> {
+        for (fd, source) in &self.0 {
+            let result = match source {
+                Source::File(file) if file.as_raw_fd() == *fd => clear_cloexec(*fd),
+                Source::File(file) => unsafe { libc::dup2(file.as_raw_fd(), *fd) },
+                Source::Dup(source) if source == fd => clear_cloexec(*fd),
+                Source::Dup(source) => unsafe { libc::dup2(*source, *fd) },
+                Source::Close => unsafe { libc::close(*fd) },
+            };
+            if result == -1 && !matches!(source, Source::Close) {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Copies of the shell's descriptors replaced by `Redirections::apply_saved`, put back
+/// when dropped.
+pub struct SavedFds(Vec<(RawFd, RawFd)>);
+
+impl Redirections {
+    /// Like `apply`, but for commands run inside the shell process itself.
+    pub fn apply_saved(&self) -> io::Result<SavedFds> {
+        let _ = io::stdout().flush();
+        let mut saved = SavedFds(Vec::new());
+        let floor = self.floor();
+        for (fd, _) in &self.0 {
+            if !saved.0.iter().any(|(saved_fd, _)| saved_fd == fd) {
+                let copy = unsafe { libc::fcntl(*fd, libc::F_DUPFD_CLOEXEC, floor) };
+                saved.0.push((*fd, copy));
+            }
+        }
+        self.apply()?;
+        Ok(saved)
+    }
+}
+
+impl Drop for SavedFds {
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+        for (fd, copy) in self.0.iter().rev() {
+            unsafe {
+                if *copy >= 0 {
+                    libc::dup2(*copy, *fd);
+                    libc::close(*copy);
+                } else {
+                    libc::close(*fd);
+                }
+            }
+        }
+    }
+}
+
+/// An anonymous temporary file holding `text`, positioned at its start. Only we can read it
+/// in the moment before it is unlinked.
+fn here_doc_file(text: &str) -> Result<File> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let path = env::temp_dir().join(format!(
+        "potato-heredoc-{}-{}",
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut file = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)?;
+    fs::remove_file(&path)?;
+    file.write_all(text.as_bytes())?;
+    file.rewind()?;
+    Ok(file)
+}
+
+fn clear_cloexec(fd: RawFd) -> i32 {
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags == -1 {
+            return -1;
+        }
+        libc::fcntl(fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `commands` in a new shell in a child process, so that redirections of builtins
+    /// leave the test's own descriptors alone. Tells whether `check` then held there.
+    fn run_in_child(commands: &str, check: impl FnOnce() -> bool) -> bool {
+        match unsafe { libc::fork() } {
+            -1 => panic!("{}", io::Error::last_os_error()),
+            0 => {
+                let mut shell = Shell::new().unwrap();
+                let ok = shell.handel_command(commands).is_ok() && check();
+                unsafe { libc::_exit(i32::from(!ok)) };
+            }
+            pid => {
+                let mut status = 0;
+                unsafe { libc::waitpid(pid, &mut status, 0) };
+                libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0
+            }
+        }
+    }
+
+    fn temp_dir(name: &str) -> String {
+        let dir = env::temp_dir().join(format!("potato-{name}-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn truncates_appends_and_reads_files() {
+        let dir = temp_dir("redirect-files");
+        let commands = format!(
+            "cd {dir}; echo one > a; echo two >> a; /bin/echo three >> a; cat < a > b; \
+             echo new > a; /bin/sh -c 'echo err >&2' 2>> b; cat nothing 2> c >&2"
+        );
+        assert!(run_in_child(&commands, || true));
+        assert_eq!(fs::read_to_string(format!("{dir}/a")).unwrap(), "new\n");
+        assert_eq!(
+            fs::read_to_string(format!("{dir}/b")).unwrap(),
+            "one\ntwo\nthree\nerr\n"
+        );
+        assert!(fs::read_to_string(format!("{dir}/c"))
+            .unwrap()
+            .contains("nothing"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn handles_descriptors_above_nine() {
+        let dir = temp_dir("redirect-fds");
+        let commands = format!(
+            "cd {dir}; echo hi 10>a >&10; cat 11<a <&11 > b; cat 3<a 12<&3 <&12 >> b; \
+             echo builtin 11>>c 12>&1 >&11; bash -c 'echo external >&13' 13>>c"
+        );
+        let closed = || (11..=13).all(|fd| unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1);
+        assert!(run_in_child(&commands, closed));
+        assert_eq!(fs::read_to_string(format!("{dir}/a")).unwrap(), "hi\n");
+        assert_eq!(fs::read_to_string(format!("{dir}/b")).unwrap(), "hi\nhi\n");
+        assert_eq!(
+            fs::read_to_string(format!("{dir}/c")).unwrap(),
+            "builtin\nexternal\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
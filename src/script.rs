@@ -0,0 +1,142 @@
+use anyhow::{anyhow, Result};
+use colored::Colorize;
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, Read},
+    mem,
+    os::fd::AsFd,
+    path::Path,
+};
+
+use crate::{
+    parser::{self, ParseError},
+    shell::Shell,
+};
+
+impl Shell {
+    /// Runs the commands read from `lines`, each as soon as it is complete. Errors are
+    /// reported with `name` and the line number. A syntax error ends the script with
+    /// status 2.
+    pub fn run_lines(
+        &mut self,
+        name: &str,
+        lines: impl IntoIterator<Item = io::Result<String>>,
+    ) -> Result<()> {
+        let mut input = String::new();
+        let mut first_line = 1;
+        for (index, line) in lines.into_iter().enumerate() {
+            let line = line?;
+            if input.is_empty() {
+                first_line = index + 1;
+            } else {
+                input.push('\n');
+            }
+            input.push_str(&line);
+            let list = match parser::parse_with_aliases(&input, &self.aliases) {
+                Ok(list) => list,
+                Err(err) if err.incomplete => continue,
+                Err(err) => {
+                    self.syntax_error(name, first_line, &input, &err);
+                    return Ok(());
+                }
+            };
+            if let Err(err) = self.run_list(&list) {
+                self.status = 1;
+                eprintln!("{}", format!("{name}:{first_line}: {err}").red());
+            }
+            input.clear();
+            if self.exit_code.is_some() || self.returning {
+                return Ok(());
+            }
+        }
+        if let Err(err) = parser::parse_with_aliases(&input, &self.aliases) {
+            self.syntax_error(name, first_line, &input, &err);
+        }
+        Ok(())
+    }
+
+    /// Runs the file at `path` in the current shell, with `args` as positional parameters if
+    /// there are any, and returns its status. A `return` in it ends it.
+    pub fn source_file(&mut self, path: &str, args: &[String]) -> Result<i32> {
+        let script = fs::read_to_string(path).map_err(|err| anyhow!("{path}: {err}"))?;
+        let positional =
+            (!args.is_empty()).then(|| mem::replace(&mut self.positional, args.to_vec()));
+        self.sourcing += 1;
+        let result = self.run_lines(path, script.lines().map(|line| Ok(line.to_owned())));
+        self.sourcing -= 1;
+        self.returning = false;
+        if let Some(positional) = positional {
+            self.positional = positional;
+        }
+        result?;
+        Ok(self.status)
+    }
+
+    /// Runs `source file [args...]` or `. file [args...]`. A name without a slash is looked
+    /// up in `$PATH`, then in the current directory.
+    pub fn builtin_source(&mut self, args: &[String]) -> Result<i32> {
+        let Some(name) = args.first() else {
+            eprintln!("source: filename argument required");
+            return Ok(2);
+        };
+        let found = if name.contains('/') {
+            None
+        } else {
+            self.vars.get("PATH").and_then(|path| {
+                env::split_paths(path)
+                    .map(|dir| dir.join(name))
+                    .find(|path| path.is_file())
+            })
+        };
+        let path = found.map_or_else(|| name.clone(), |path| path.to_string_lossy().into_owned());
+        self.source_file(&path, &args[1..])
+    }
+
+    /// Runs the startup file at `path` if it exists, reporting errors without stopping.
+    pub fn run_startup_file(&mut self, path: &Path) {
+        if !path.exists() {
+            return;
+        }
+        if let Err(err) = self.source_file(&path.to_string_lossy(), &[]) {
+            eprintln!("{}", err.to_string().red());
+        }
+    }
+
+    /// Reports `err` in `input`, which starts at line `first_line` of `name`.
+    fn syntax_error(&mut self, name: &str, first_line: usize, input: &str, err: &ParseError) {
+        let line = first_line + input[..err.span.start].matches('\n').count();
+        eprintln!("{}", format!("{name}:{line}: {}", err.render(input)).red());
+        self.status = 2;
+    }
+}
+
+/// The lines of standard input, read one byte at a time so that commands run from them
+/// can read the rest of the input themselves, as in `cat` followed by data lines.
+pub struct StdinLines(File);
+
+impl StdinLines {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self(File::from(io::stdin().as_fd().try_clone_to_owned()?)))
+    }
+}
+
+impl Iterator for StdinLines {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut line = Vec::new();
+        let mut byte = [0];
+        loop {
+            match self.0.read(&mut byte) {
+                Ok(0) if line.is_empty() => return None,
+                Ok(0) => break,
+                Ok(_) if byte[0] == b'\n' => break,
+                Ok(_) => line.push(byte[0]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        Some(Ok(String::from_utf8_lossy(&line).into_owned()))
+    }
+}
@@ -0,0 +1,107 @@
+use anyhow::Result;
+use chrono::Local;
+use libc::pid_t;
+use rustyline::{history::FileHistory, DefaultEditor, Editor};
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    path::PathBuf,
+};
+
+use crate::{
+    compound::LoopControl,
+    history::History,
+    jobs::Job,
+    options::Options,
+    parser::ast::Function,
+    vars::{Var, Variables},
+};
+
+pub struct Shell {
+    pub rl: Editor<(), FileHistory>,
+    pub current_path: String,
+    pub status: i32,
+    pub pipe_status: Vec<i32>,
+    pub exit_code: Option<i32>,
+    /// Job control is enabled: the shell reads from a terminal and runs jobs in their own
+    /// process groups.
+    pub interactive: bool,
+    pub pgid: pid_t,
+    pub jobs: Vec<Job>,
+    pub last_background: Option<pid_t>,
+    /// Status of the last command substitution in the command being expanded.
+    pub substitution_status: Option<i32>,
+    pub vars: Variables,
+    pub options: Options,
+    /// Process id of the shell, the value of `$$`.
+    pub pid: u32,
+    /// Number of loops the running command is nested in.
+    pub loop_depth: usize,
+    pub loop_control: Option<LoopControl>,
+    pub functions: HashMap<String, Function>,
+    /// The value of `$0`.
+    pub arg0: String,
+    /// `$1`, `$2` and so on.
+    pub positional: Vec<String>,
+    /// One frame per running function call, holding what its `local` variables replaced.
+    pub frames: Vec<Vec<(String, Option<Var>)>>,
+    /// A `return` is leaving the running function or sourced file.
+    pub returning: bool,
+    /// Number of files being run by `source`.
+    pub sourcing: usize,
+    pub aliases: BTreeMap<String, String>,
+    pub history: Vec<History>,
+    /// Tells this shell's history entries from those of other sessions.
+    pub session: String,
+    /// The file history is kept in, once loaded from it.
+    pub history_file: Option<PathBuf>,
+    /// How much of the history file has been read, to find what other sessions add.
+    pub history_read: u64,
+    /// The device and inode of the history file that was read, to tell when it has been
+    /// replaced by another file.
+    pub history_id: (u64, u64),
+    /// `fc` put the commands it ran in the history, in place of the line that ran it.
+    pub fc_ran: bool,
+}
+
+impl Shell {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            rl: DefaultEditor::new()?,
+            current_path: "/".to_owned(),
+            status: 0,
+            pipe_status: Vec::new(),
+            exit_code: None,
+            interactive: false,
+            pgid: 0,
+            jobs: Vec::new(),
+            last_background: None,
+            substitution_status: None,
+            vars: Variables::from_env(),
+            options: Options::default(),
+            pid: std::process::id(),
+            loop_depth: 0,
+            loop_control: None,
+            functions: HashMap::new(),
+            arg0: env::args()
+                .next()
+                .unwrap_or_else(|| "potato-shell".to_owned()),
+            positional: Vec::new(),
+            frames: Vec::new(),
+            returning: false,
+            sourcing: 0,
+            aliases: BTreeMap::new(),
+            history: Vec::new(),
+            history_file: None,
+            history_read: 0,
+            history_id: (0, 0),
+            fc_ran: false,
+            session: format!(
+                "{}-{}",
+                Local::now().format("%Y%m%d%H%M%S"),
+                std::process::id()
+            ),
+        })
+    }
+}
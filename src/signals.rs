@@ -0,0 +1,38 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Signals an interactive shell must survive; they are meant for the foreground job.
+const IGNORED: [i32; 4] = [libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU, libc::SIGQUIT];
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Sets up the dispositions of an interactive shell: job control and quit signals are
+/// ignored, and SIGINT is only recorded so the shell keeps running. Blocking calls such as
+/// `waitpid` are not restarted after SIGINT, which lets builtins like `wait` be interrupted.
+pub fn ignore_interactive() {
+    unsafe {
+        for signal in IGNORED {
+            libc::signal(signal, libc::SIG_IGN);
+        }
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = 0;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut());
+    }
+}
+
+/// Restores default dispositions in a child. Safe to call between `fork` and `exec`.
+pub fn reset() {
+    for signal in IGNORED.into_iter().chain([libc::SIGINT]) {
+        unsafe { libc::signal(signal, libc::SIG_DFL) };
+    }
+}
+
+/// Whether SIGINT arrived since the last call.
+pub fn take_interrupt() -> bool {
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
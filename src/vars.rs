@@ -0,0 +1,92 @@
+use std::{collections::HashMap, env};
+
+#[derive(Debug, Clone, Default)]
+pub struct Var {
+    pub value: String,
+    pub exported: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Variables {
+    vars: HashMap<String, Var>,
+}
+
+impl Variables {
+    /// Starts with every variable of the process environment, all exported.
+    pub fn from_env() -> Self {
+        let vars = env::vars()
+            .map(|(name, value)| {
+                (
+                    name,
+                    Var {
+                        value,
+                        exported: true,
+                    },
+                )
+            })
+            .collect();
+        Self { vars }
+    }
+
+    pub fn var(&self, name: &str) -> Option<&Var> {
+        self.vars.get(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(|var| var.value.as_str())
+    }
+
+    /// Sets `name`, keeping its export flag if it already exists.
+    pub fn set(&mut self, name: &str, value: impl Into<String>) {
+        self.vars.entry(name.to_owned()).or_default().value = value.into();
+    }
+
+    pub fn export(&mut self, name: &str, exported: bool) {
+        self.vars.entry(name.to_owned()).or_default().exported = exported;
+    }
+
+    pub fn unset(&mut self, name: &str) -> Option<Var> {
+        self.vars.remove(name)
+    }
+
+    pub fn restore(&mut self, name: &str, var: Option<Var>) {
+        match var {
+            Some(var) => {
+                self.vars.insert(name.to_owned(), var);
+            }
+            None => {
+                self.vars.remove(name);
+            }
+        }
+    }
+
+    /// All variables, sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Var)> {
+        let mut vars: Vec<_> = self
+            .vars
+            .iter()
+            .map(|(name, var)| (name.as_str(), var))
+            .collect();
+        vars.sort_by_key(|(name, _)| *name);
+        vars.into_iter()
+    }
+
+    /// The environment passed to child processes.
+    pub fn exported(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.iter()
+            .filter(|(_, var)| var.exported)
+            .map(|(name, var)| (name, var.value.as_str()))
+    }
+}
+
+/// Quotes `value` so the shell reads it back as a single word.
+pub fn quote(value: &str) -> String {
+    if !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./:,+@%=".contains(c))
+    {
+        return value.to_owned();
+    }
+    format!("'{}'", value.replace('\'', r"'\''"))
+}